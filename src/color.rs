@@ -16,13 +16,29 @@
 use std::fmt::{self, Display, Formatter};
 
 pub trait Color: Display {
-    fn red(&self) -> Red<Self> {
+    fn red(&self) -> Red<'_, Self> {
         Red { t: self }
     }
 
-    fn green(&self) -> Green<Self> {
+    fn green(&self) -> Green<'_, Self> {
         Green { t: self }
     }
+
+    fn yellow(&self) -> Yellow<'_, Self> {
+        Yellow { t: self }
+    }
+
+    fn blue(&self) -> Blue<'_, Self> {
+        Blue { t: self }
+    }
+
+    fn magenta(&self) -> Magenta<'_, Self> {
+        Magenta { t: self }
+    }
+
+    fn bold(&self) -> Bold<'_, Self> {
+        Bold { t: self }
+    }
 }
 
 impl<T: Display> Color for T {}
@@ -46,3 +62,44 @@ impl<'a, T: Display + ?Sized> Display for Green<'a, T> {
         write!(f, "\x1b[32m{}\x1b[0m", self.t)
     }
 }
+
+pub struct Yellow<'a, T: Display + ?Sized> {
+    t: &'a T,
+}
+
+impl<'a, T: Display + ?Sized> Display for Yellow<'a, T> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "\x1b[33m{}\x1b[0m", self.t)
+    }
+}
+
+pub struct Blue<'a, T: Display + ?Sized> {
+    t: &'a T,
+}
+
+impl<'a, T: Display + ?Sized> Display for Blue<'a, T> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "\x1b[34m{}\x1b[0m", self.t)
+    }
+}
+
+pub struct Magenta<'a, T: Display + ?Sized> {
+    t: &'a T,
+}
+
+impl<'a, T: Display + ?Sized> Display for Magenta<'a, T> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "\x1b[35m{}\x1b[0m", self.t)
+    }
+}
+
+pub struct Bold<'a, T: Display + ?Sized> {
+    t: &'a T,
+}
+
+// only resets the intensity, so it can be nested inside a color
+impl<'a, T: Display + ?Sized> Display for Bold<'a, T> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "\x1b[1m{}\x1b[22m", self.t)
+    }
+}
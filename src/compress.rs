@@ -16,17 +16,20 @@
 use std::{
     borrow::Borrow,
     cmp,
-    ffi::{OsStr, OsString},
+    ffi::{CStr, CString, OsStr, OsString},
     fs::{self, Metadata},
     io::{self, ErrorKind},
     os::unix::ffi::OsStrExt,
     path::{Path, PathBuf},
-    str,
+    str::{self, FromStr},
 };
 
 mod gct;
 
+use crate::color::Color;
 use gct::GraphemeClusterTrie;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 
 pub trait IntoStringLossy {
     fn into_string_lossy(self) -> String;
@@ -45,122 +48,511 @@ impl IntoStringLossy for PathBuf {
     }
 }
 
-pub fn compress(path: &Path, min_home_dir_uid: u64, max_home_dir_uid: u64) -> io::Result<String> {
-    let (without_prefix, mut buf, mut compressed) =
-        without_prefix(path, min_home_dir_uid, max_home_dir_uid)?;
+// a directory shown as a label instead, like zsh's named directories
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Alias {
+    label: String,
+    path: PathBuf,
+}
 
-    let mut components: Vec<_> = without_prefix.components().collect();
+// LABEL=PATH, where PATH may start with ~/. LABEL starts with ~, like a home directory, so
+// expanding a relative path never mistakes its first component for an alias
+impl FromStr for Alias {
+    type Err = ();
 
-    if let Some(last) = components.pop() {
-        'outer: for component in components.into_iter() {
-            let component_os = component.as_os_str();
-            compressed.push('/');
+    fn from_str(s: &str) -> Result<Alias, ()> {
+        let (label, path) = s.split_once('=').ok_or(())?;
 
-            let entries = match buf.read_dir() {
-                Ok(e) => e,
-                Err(_) => {
-                    buf.push(component);
-                    compressed.push_str(component_os.to_string_lossy().borrow());
+        let path = match (path.strip_prefix("~/"), dirs::home_dir()) {
+            (Some(rest), Some(home_dir)) => home_dir.join(rest),
+            _ => PathBuf::from(path),
+        };
 
-                    continue;
-                }
-            };
+        if label.len() < 2 || !label.starts_with('~') || label.contains('/') || !path.is_absolute()
+        {
+            return Err(());
+        }
+
+        Ok(Alias {
+            label: label.to_string(),
+            path,
+        })
+    }
+}
+
+// with a max_width, components are only abbreviated as far as it takes to fit
+pub fn compress<S: Strategy + ?Sized>(
+    path: &Path,
+    min_home_dir_uid: u64,
+    max_home_dir_uid: u64,
+    aliases: &[Alias],
+    max_width: Option<usize>,
+    strategy: &mut S,
+    anchors: Option<&Anchors>,
+) -> io::Result<String> {
+    let (without_prefix, buf, compressed) =
+        without_prefix(path, min_home_dir_uid, max_home_dir_uid, aliases)?;
+
+    Ok(compress_components(
+        without_prefix,
+        buf,
+        compressed,
+        max_width,
+        strategy,
+        anchors,
+    ))
+}
 
-            let mut filenames = Vec::new();
+// like compress, but starting from root, which is shown as root_name. None if path isn't in root
+pub fn compress_within<S: Strategy + ?Sized>(
+    path: &Path,
+    root: &Path,
+    root_name: String,
+    max_width: Option<usize>,
+    strategy: &mut S,
+    anchors: Option<&Anchors>,
+) -> Option<String> {
+    let within = path.strip_prefix(root).ok()?;
+
+    Some(compress_components(
+        within,
+        root.to_path_buf(),
+        root_name,
+        max_width,
+        strategy,
+        anchors,
+    ))
+}
 
-            for maybe_entry in entries {
-                let entry = match maybe_entry {
-                    Ok(e) => e,
-                    Err(_) => {
-                        buf.push(component);
-                        compressed.push_str(component_os.to_string_lossy().borrow());
+// abbreviates components of path but the last, from the left, until the whole thing fits in
+// max_width or there's nothing left to abbreviate. buf is the directory path is relative to and
+// prefix is how it's shown. if that's still too wide, leading components are elided
+fn compress_components<S: Strategy + ?Sized>(
+    path: &Path,
+    mut buf: PathBuf,
+    mut prefix: String,
+    max_width: Option<usize>,
+    strategy: &mut S,
+    anchors: Option<&Anchors>,
+) -> String {
+    let components: Vec<_> = path.components().map(|c| c.as_os_str()).collect();
+
+    let dropped = strategy
+        .max_components()
+        .map_or(0, |n| components.len().saturating_sub(n));
+
+    if dropped > 0 {
+        prefix = ELLIPSIS.to_string();
+        buf.extend(&components[..dropped]);
+    }
 
-                        continue 'outer;
-                    }
-                };
+    let components = &components[dropped..];
+    let mut parts = Vec::with_capacity(components.len());
+    // the directory each component is in
+    let mut parents = Vec::with_capacity(components.len());
+    let mut is_anchor = Vec::with_capacity(components.len());
 
-                let filename = entry.file_name();
+    for component in components {
+        let name = component.to_string_lossy();
 
-                if filename == component.as_os_str() {
-                    continue;
-                }
+        parents.push(buf.clone());
+        buf.push(component);
 
-                if fs::metadata(&entry.path())
-                    .as_ref()
-                    .map(Metadata::is_dir)
-                    .unwrap_or(true)
-                {
-                    filenames.push(filename.into_string_lossy());
-                }
+        match anchors {
+            Some(anchors) if anchors.contains(&buf) => {
+                parts.push(anchors.show(&name));
+                is_anchor.push(true);
             }
+            _ => {
+                parts.push(name.into_owned());
+                is_anchor.push(false);
+            }
+        }
+    }
 
-            let trie: GraphemeClusterTrie = filenames.iter().map(|s| s.as_str()).collect();
-            let component_cow = component_os.to_string_lossy();
-            let component_str = component_cow.borrow();
+    // every part is preceded by a slash
+    let fits = |prefix: &str, parts: &[String]| match max_width {
+        Some(max_width) => {
+            width(prefix) + parts.iter().map(|p| width(p) + 1).sum::<usize>() <= max_width
+        }
+        None => false,
+    };
 
-            if let Some(mut prefix) = trie.shortest_unique_prefix(component_str) {
-                // avoid compressing ".a" to "." or "..a" to "."/".."
-                if prefix.starts_with('.') {
-                    const MIN_DISAMBUGABLE_LEN: usize = 3;
+    for (i, component) in components
+        .iter()
+        .enumerate()
+        .take(parts.len().saturating_sub(1))
+    {
+        if fits(&prefix, &parts) {
+            break;
+        }
 
-                    let search_len = cmp::min(component_str.len(), MIN_DISAMBUGABLE_LEN);
+        if !is_anchor[i] {
+            parts[i] = strategy.abbreviate(&parents[i], component);
+        }
+    }
 
-                    if let Some(last_dot_index) = component_str[..search_len].rfind('.') {
-                        let ideal_end_index = cmp::min(last_dot_index + 2, component_str.len());
+    // always keep the last component, however wide it is
+    if max_width.is_some() && !parts.is_empty() && !fits(&prefix, &parts) {
+        let mut elided = 0;
 
-                        let disambugable: &str =
-                            &component_str[..cmp::min(ideal_end_index, MIN_DISAMBUGABLE_LEN)];
+        while elided + 1 < parts.len() && !fits(ELLIPSIS, &parts[elided..]) {
+            elided += 1;
+        }
 
-                        if disambugable.len() > prefix.len() {
-                            prefix = disambugable;
-                        }
-                    } else {
-                        prefix =
-                            &component_str[..cmp::min(component_str.len(), MIN_DISAMBUGABLE_LEN)];
-                    }
-                }
+        prefix = ELLIPSIS.to_string();
+        parts.drain(..elided);
+    }
+
+    let mut compressed = prefix;
+
+    if parts.is_empty() && compressed.is_empty() {
+        compressed.push('/');
+    }
+
+    for part in parts {
+        compressed.push('/');
+        compressed.push_str(&part);
+    }
+
+    compressed
+}
+
+// directories that are never compressed, because they hold a marker like .git or Cargo.toml
+// and so are usually where a project starts
+pub struct Anchors {
+    markers: Vec<OsString>,
+    bold: bool,
+}
+
+impl Anchors {
+    pub fn new(markers: Vec<OsString>, bold: bool) -> Anchors {
+        Anchors { markers, bold }
+    }
+
+    fn contains(&self, dir: &Path) -> bool {
+        self.markers.iter().any(|m| dir.join(m).exists())
+    }
+
+    fn show(&self, name: &str) -> String {
+        if self.bold {
+            name.bold().to_string()
+        } else {
+            name.to_string()
+        }
+    }
+}
+
+const ELLIPSIS: &str = "…";
+
+// in terminal columns, so wide characters like CJK count twice, not counting escape sequences
+// like the ones that embolden a repository's name
+pub fn width(s: &str) -> usize {
+    let mut visible = String::with_capacity(s.len());
+    let mut in_escape = false;
+
+    for c in s.chars() {
+        if in_escape {
+            in_escape = !c.is_ascii_alphabetic();
+        } else if c == '\x1b' {
+            in_escape = true;
+        } else {
+            visible.push(c);
+        }
+    }
+
+    UnicodeWidthStr::width(visible.as_str())
+}
+
+// how each directory in a path is shortened
+pub trait Strategy {
+    // component is a directory in dir
+    fn abbreviate(&mut self, dir: &Path, component: &OsStr) -> String;
+
+    // if set, only this many components are kept from the end and the rest are elided
+    fn max_components(&self) -> Option<usize> {
+        None
+    }
+}
+
+// closures like abbreviate, or one that asks the daemon first
+impl<F: FnMut(&Path, &OsStr) -> String> Strategy for F {
+    fn abbreviate(&mut self, dir: &Path, component: &OsStr) -> String {
+        self(dir, component)
+    }
+}
+
+// the first few graphemes of each directory
+pub struct FixedLength(pub usize);
+
+impl Strategy for FixedLength {
+    fn abbreviate(&mut self, _: &Path, component: &OsStr) -> String {
+        leading_graphemes(&component.to_string_lossy(), self.0)
+    }
+}
+
+// like fish's prompt_pwd
+pub struct FirstGrapheme;
+
+impl Strategy for FirstGrapheme {
+    fn abbreviate(&mut self, _: &Path, component: &OsStr) -> String {
+        leading_graphemes(&component.to_string_lossy(), 1)
+    }
+}
+
+// whole directories, but only the last few of them
+pub struct LastComponents(pub usize);
+
+impl Strategy for LastComponents {
+    fn abbreviate(&mut self, _: &Path, component: &OsStr) -> String {
+        component.to_string_lossy().into_owned()
+    }
+
+    fn max_components(&self) -> Option<usize> {
+        Some(self.0)
+    }
+}
+
+pub struct NoCompression;
+
+impl Strategy for NoCompression {
+    fn abbreviate(&mut self, _: &Path, component: &OsStr) -> String {
+        component.to_string_lossy().into_owned()
+    }
+}
 
-                compressed.push_str(prefix);
+// leading dots don't count, so hidden directories don't all become "."
+fn leading_graphemes(component: &str, count: usize) -> String {
+    let dots = component.len() - component.trim_start_matches('.').len();
+
+    component[..dots]
+        .chars()
+        .chain(
+            component[dots..]
+                .graphemes(true)
+                .take(count)
+                .flat_map(str::chars),
+        )
+        .collect()
+}
+
+// shortens component, a directory in dir, to its shortest unique prefix among the other
+// directories in dir
+pub fn abbreviate(dir: &Path, component: &OsStr) -> String {
+    let component_cow = component.to_string_lossy();
+    let component_str: &str = component_cow.borrow();
+
+    let entries = match dir.read_dir() {
+        Ok(e) => e,
+        Err(_) => return component_str.to_string(),
+    };
+
+    let mut filenames = Vec::new();
+
+    for maybe_entry in entries {
+        let entry = match maybe_entry {
+            Ok(e) => e,
+            Err(_) => return component_str.to_string(),
+        };
+
+        let filename = entry.file_name();
+
+        if filename == component {
+            continue;
+        }
+
+        if fs::metadata(entry.path())
+            .as_ref()
+            .map(Metadata::is_dir)
+            .unwrap_or(true)
+        {
+            filenames.push(filename.into_string_lossy());
+        }
+    }
+
+    let trie: GraphemeClusterTrie = filenames.iter().map(|s| s.as_str()).collect();
+
+    if let Some(mut prefix) = trie.shortest_unique_prefix(component_str) {
+        // avoid compressing ".a" to "." or "..a" to "."/".."
+        if prefix.starts_with('.') {
+            const MIN_DISAMBUGABLE_LEN: usize = 3;
+
+            let search_len = cmp::min(component_str.len(), MIN_DISAMBUGABLE_LEN);
+
+            if let Some(last_dot_index) = component_str[..search_len].rfind('.') {
+                let ideal_end_index = cmp::min(last_dot_index + 2, component_str.len());
+
+                let disambugable: &str =
+                    &component_str[..cmp::min(ideal_end_index, MIN_DISAMBUGABLE_LEN)];
+
+                if disambugable.len() > prefix.len() {
+                    prefix = disambugable;
+                }
             } else {
-                compressed.push_str(component_str.borrow());
+                prefix = &component_str[..cmp::min(component_str.len(), MIN_DISAMBUGABLE_LEN)];
             }
+        }
 
-            buf.push(component);
+        prefix.to_string()
+    } else {
+        component_str.to_string()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ExpandError {
+    // no directory in the first path starts with the component
+    NotFound(PathBuf, String),
+    // every directory the component could stand for
+    Ambiguous(Vec<PathBuf>),
+}
+
+// the inverse of compress: resolves each component of compressed to the directory it's a
+// prefix of. relative paths are resolved from working_directory
+pub fn expand(
+    compressed: &str,
+    working_directory: &Path,
+    aliases: &[Alias],
+) -> Result<PathBuf, ExpandError> {
+    let alias = aliases
+        .iter()
+        .filter(|a| match compressed.strip_prefix(a.label.as_str()) {
+            Some(rest) => rest.is_empty() || rest.starts_with('/'),
+            None => false,
+        })
+        .max_by_key(|a| a.label.len());
+
+    let (mut path, rest) = if let Some(alias) = alias {
+        (alias.path.clone(), &compressed[alias.label.len()..])
+    } else if compressed.starts_with('/') {
+        (PathBuf::from("/"), compressed)
+    } else if let Some(rest) = compressed.strip_prefix('~') {
+        let (username, rest) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+
+        let home_dir = if username.is_empty() {
+            dirs::home_dir()
+        } else {
+            home_dir_of(username)
+        };
+
+        match home_dir {
+            Some(h) => (h, rest),
+            None => {
+                return Err(ExpandError::NotFound(
+                    PathBuf::from("/"),
+                    compressed.to_string(),
+                ))
+            }
         }
+    } else {
+        (working_directory.to_path_buf(), compressed)
+    };
 
-        compressed.push('/');
-        compressed.push_str(last.as_os_str().to_string_lossy().borrow());
-    } else if compressed.is_empty() {
-        compressed.push('/');
+    for component in rest.split('/').filter(|c| !c.is_empty()) {
+        let exact = path.join(component);
+
+        // compressed components are never a whole other directory's name, so a directory
+        // that matches exactly is the one that was meant
+        if component == "." || component == ".." || exact.is_dir() {
+            path = exact;
+
+            continue;
+        }
+
+        let mut candidates: Vec<_> = match path.read_dir() {
+            Ok(entries) => entries
+                .filter_map(Result::ok)
+                .filter(|e| e.file_name().as_bytes().starts_with(component.as_bytes()))
+                .map(|e| e.path())
+                .filter(|p| p.is_dir())
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+
+        match candidates.len() {
+            0 => return Err(ExpandError::NotFound(path, component.to_string())),
+            1 => path = candidates.pop().unwrap(),
+            _ => {
+                candidates.sort();
+
+                return Err(ExpandError::Ambiguous(candidates));
+            }
+        }
     }
 
-    Ok(compressed)
+    Ok(path)
 }
 
-fn without_prefix(
-    path: &Path,
+fn home_dir_of(username: &str) -> Option<PathBuf> {
+    let username = CString::new(username).ok()?;
+    let passwd = unsafe { libc::getpwnam(username.as_ptr()) };
+
+    if passwd.is_null() {
+        return None;
+    }
+
+    let home_dir = unsafe { CStr::from_ptr((*passwd).pw_dir) };
+
+    Some(PathBuf::from(OsStr::from_bytes(home_dir.to_bytes())))
+}
+
+// strips the longest prefix that's an alias or the user's home directory, or failing that,
+// another user's home directory. returns what's left, the prefix and how it's shown
+fn without_prefix<'a>(
+    path: &'a Path,
     min_home_dir_uid: u64,
     max_home_dir_uid: u64,
-) -> io::Result<(&Path, PathBuf, String)> {
+    aliases: &[Alias],
+) -> io::Result<(&'a Path, PathBuf, String)> {
+    // in order of preference between prefixes that are just as long
+    let mut candidates = Vec::new();
+
+    for alias in aliases {
+        if let Ok(without_prefix) = path.strip_prefix(&alias.path) {
+            candidates.push((without_prefix, alias.path.clone(), alias.label.clone()));
+        }
+    }
+
+    let mut is_in_home_dir = false;
+
     if let Some(home_dir) = dirs::home_dir() {
         if let Ok(without_prefix) = path.strip_prefix(&home_dir) {
-            return Ok((without_prefix, home_dir, "~".to_string()));
+            candidates.push((without_prefix, home_dir, "~".to_string()));
+            is_in_home_dir = true;
         }
     }
 
-    let default_prefixed = || {
-        path.strip_prefix(OsStr::from_bytes(b"/").as_ref() as &Path)
-            .map(move |p| (p, "/".into(), String::new()))
-            .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
-    };
+    // reading /etc/passwd on every prompt adds up, and usually the path is in our own home.
+    // an alias can still be outdone by a home directory under it, like /srv/bob under /srv
+    if !is_in_home_dir {
+        candidates.extend(other_home_dirs(path, min_home_dir_uid, max_home_dir_uid));
+    }
 
-    let passwd_contents = if let Ok(contents) = fs::read("/etc/passwd") {
-        contents
-    } else {
-        return default_prefixed();
+    // the longest prefix leaves the least behind. min_by_key keeps the first of equals
+    if let Some(candidate) = candidates
+        .into_iter()
+        .min_by_key(|(without_prefix, _, _)| without_prefix.as_os_str().len())
+    {
+        return Ok(candidate);
+    }
+
+    path.strip_prefix(OsStr::from_bytes(b"/").as_ref() as &Path)
+        .map(move |p| (p, "/".into(), String::new()))
+        .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+}
+
+// the home directories in /etc/passwd that contain path, ordered by username
+fn other_home_dirs(
+    path: &Path,
+    min_home_dir_uid: u64,
+    max_home_dir_uid: u64,
+) -> Vec<(&Path, PathBuf, String)> {
+    let passwd_contents = match fs::read("/etc/passwd") {
+        Ok(contents) => contents,
+        Err(_) => return Vec::new(),
     };
 
-    let without_prefix_home_dir_and_username: Vec<_> = passwd_contents
+    let mut without_prefix_home_dir_and_username: Vec<_> = passwd_contents
         .split(|&elem| elem == b'\n')
         .filter_map(|line| {
             if line.is_empty() {
@@ -192,19 +584,203 @@ fn without_prefix(
         })
         .collect();
 
-    // remove the longest possible prefix. break ties by lexicographically comparing usernames
-    if let Some((without_prefix, home_dir, username_bytes)) = without_prefix_home_dir_and_username
+    // break ties by lexicographically comparing usernames
+    without_prefix_home_dir_and_username.sort_by_key(|&(_, _, username)| username);
+
+    without_prefix_home_dir_and_username
         .into_iter()
-        .min_by_key(|&(without_prefix, _, username)| {
-            let without_prefix_len = without_prefix.as_os_str().len();
+        .map(|(without_prefix, home_dir, username_bytes)| {
+            let username = String::from_utf8_lossy(username_bytes);
 
-            (without_prefix_len, username)
+            (without_prefix, home_dir, format!("~{}", username))
         })
-    {
-        let username = String::from_utf8_lossy(username_bytes);
+        .collect()
+}
 
-        Ok((without_prefix, home_dir, format!("~{}", username)))
-    } else {
-        default_prefixed()
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{env, process};
+
+    #[test]
+    fn compress_to_width() {
+        let root = env::temp_dir().join(format!("clanker-width-{}", process::id()));
+        let _ = fs::remove_dir_all(&root);
+
+        for dir in ["alpha/beta/gamma", "alps", "bravo"].iter() {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+
+        let path = Path::new("alpha/beta/gamma");
+        let compress = |max_width| {
+            compress_components(
+                path,
+                root.clone(),
+                "~".to_string(),
+                max_width,
+                &mut abbreviate,
+                None,
+            )
+        };
+
+        assert_eq!(compress(None), "~/alph/b/gamma");
+        assert_eq!(compress(Some(100)), "~/alpha/beta/gamma");
+        // only as far left as it takes
+        assert_eq!(compress(Some(17)), "~/alph/beta/gamma");
+        assert_eq!(compress(Some(16)), "~/alph/b/gamma");
+        assert_eq!(compress(Some(9)), "…/b/gamma");
+        assert_eq!(compress(Some(3)), "…/gamma");
+
+        // a long prefix goes first
+        let someone = compress_components(
+            path,
+            root.clone(),
+            "~someone".into(),
+            Some(14),
+            &mut abbreviate,
+            None,
+        );
+        assert_eq!(someone, "…/alph/b/gamma");
+
+        assert_eq!(width("\x1b[1mclanker\x1b[22m/…"), 9);
+        // wide characters take two columns and combining marks none
+        assert_eq!(width("文書/e\u{301}"), 6);
+
+        fs::write(root.join("alpha/beta/Cargo.toml"), "").unwrap();
+
+        let anchored = |max_width, bold| {
+            let anchors = Anchors::new(vec!["Cargo.toml".into()], bold);
+
+            compress_components(
+                path,
+                root.clone(),
+                "~".to_string(),
+                max_width,
+                &mut abbreviate,
+                Some(&anchors),
+            )
+        };
+
+        assert_eq!(anchored(None, false), "~/alph/beta/gamma");
+        assert_eq!(anchored(None, true), "~/alph/\x1b[1mbeta\x1b[22m/gamma");
+        // but they're elided like anything else when nothing else will do
+        assert_eq!(anchored(Some(12), true), "…/\x1b[1mbeta\x1b[22m/gamma");
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn strategies() {
+        let path = Path::new("alpha/.beta/gamma");
+        let compress = |strategy: &mut dyn Strategy| {
+            compress_components(path, "/".into(), "~".into(), None, strategy, None)
+        };
+
+        assert_eq!(compress(&mut FixedLength(3)), "~/alp/.bet/gamma");
+        assert_eq!(compress(&mut FirstGrapheme), "~/a/.b/gamma");
+        assert_eq!(compress(&mut LastComponents(2)), "…/.beta/gamma");
+        assert_eq!(compress(&mut LastComponents(3)), "~/alpha/.beta/gamma");
+        assert_eq!(compress(&mut NoCompression), "~/alpha/.beta/gamma");
+    }
+
+    #[test]
+    fn expand_prefixes() {
+        let root = env::temp_dir().join(format!("clanker-expand-{}", process::id()));
+        let _ = fs::remove_dir_all(&root);
+
+        for dir in [
+            "src/compress",
+            "src/compressed",
+            "source",
+            "s",
+            "target/debug",
+        ]
+        .iter()
+        {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+
+        // files aren't directories to cd into
+        fs::write(root.join("target/debug.txt"), "").unwrap();
+
+        assert_eq!(expand("t/d", &root, &[]), Ok(root.join("target/debug")));
+        assert_eq!(
+            expand("sr/compress", &root, &[]),
+            Ok(root.join("src/compress"))
+        );
+        assert_eq!(expand("s", &root, &[]), Ok(root.join("s")));
+        assert_eq!(
+            expand(&format!("{}/so/..", root.display()), Path::new("/"), &[]),
+            Ok(root.join("source/.."))
+        );
+        assert_eq!(
+            expand("src/comp", &root, &[]),
+            Err(ExpandError::Ambiguous(vec![
+                root.join("src/compress"),
+                root.join("src/compressed"),
+            ]))
+        );
+        assert_eq!(
+            expand("x/y", &root, &[]),
+            Err(ExpandError::NotFound(root.clone(), "x".to_string()))
+        );
+
+        let aliases: Vec<Alias> = vec![
+            format!("~proj={}", root.display()).parse().unwrap(),
+            format!("~src={}", root.join("src").display())
+                .parse()
+                .unwrap(),
+        ];
+
+        assert_eq!(
+            expand("~src/compresse", Path::new("/"), &aliases),
+            Ok(root.join("src/compressed"))
+        );
+        assert_eq!(expand("~proj", Path::new("/"), &aliases), Ok(root.clone()));
+        // relative paths are left alone, even where they look like a label
+        assert_eq!(expand("src", &root, &aliases), Ok(root.join("src")));
+
+        // the longest prefix wins
+        let compress_dir = root.join("src/compress");
+        assert_eq!(
+            without_prefix(&compress_dir, 1000, 60000, &aliases).unwrap(),
+            (Path::new("compress"), root.join("src"), "~src".to_string())
+        );
+
+        assert_eq!("~proj".parse::<Alias>(), Err(()));
+        assert_eq!("~proj=relative".parse::<Alias>(), Err(()));
+        assert_eq!("proj=/srv".parse::<Alias>(), Err(()));
+        assert_eq!("~=/srv".parse::<Alias>(), Err(()));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn home_dir_under_alias() {
+        let passwd = fs::read_to_string("/etc/passwd").unwrap_or_default();
+        let own_home_dir = dirs::home_dir().and_then(|h| fs::canonicalize(h).ok());
+
+        // another user's home directory that isn't at the top level, so an alias can contain it
+        let home_dir = passwd
+            .lines()
+            .filter_map(|line| fs::canonicalize(line.split(':').nth(5)?).ok())
+            .find(|h| {
+                h.parent() != Some(Path::new("/")) && own_home_dir.iter().all(|o| !h.starts_with(o))
+            });
+
+        let home_dir = match home_dir {
+            Some(h) => h,
+            None => return,
+        };
+
+        let alias: Alias = format!("~up={}", home_dir.parent().unwrap().display())
+            .parse()
+            .unwrap();
+        let path = home_dir.join("x");
+        let (rest, prefix, label) = without_prefix(&path, 0, u64::MAX, &[alias]).unwrap();
+
+        assert_eq!((rest, prefix), (Path::new("x"), home_dir));
+        assert_ne!(label, "~up");
     }
 }
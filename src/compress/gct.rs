@@ -80,7 +80,7 @@ mod tests {
 
     #[test]
     fn basic() {
-        let trie: GraphemeClusterTrie = ["aa", "ab", "ac"].into_iter().map(|&s| s).collect();
+        let trie: GraphemeClusterTrie = ["aa", "ab", "ac"].iter().copied().collect();
 
         assert_eq!(trie.shortest_unique_prefix("ad"), Some("ad"));
         assert_eq!(trie.shortest_unique_prefix("b"), Some("b"));
@@ -110,8 +110,8 @@ mod tests {
             "Templates",
             "Videos",
         ]
-        .into_iter()
-        .map(|&s| s)
+        .iter()
+        .copied()
         .collect();
 
         assert_eq!(trie.shortest_unique_prefix("c++"), Some("c+"));
@@ -0,0 +1,230 @@
+// Copyright (C) 2020 Gregory Meyer
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::{
+    env,
+    ffi::OsStr,
+    io::{self, BufRead, BufReader, Write},
+    os::unix::{ffi::OsStrExt, net::UnixStream},
+    path::{Path, PathBuf},
+    str,
+    time::Duration,
+};
+
+use crate::git::{Repository, StatusOptions, StatusSummary, Untracked};
+
+#[cfg(target_os = "linux")]
+mod server;
+
+#[cfg(target_os = "linux")]
+pub use server::serve;
+
+// requests and responses are sequences of NUL-terminated fields, since NUL is the only byte
+// that can't appear in a path. an empty response means the daemon couldn't answer
+//
+//   abbreviate DIRECTORY COMPONENT -> ABBREVIATION
+//   status WORKDIR UNTRACKED DIRTY_ONLY -> TREE_STATUS
+
+// a daemon that is slower than reading the directory ourselves isn't worth waiting for
+const ABBREVIATE_TIMEOUT: Duration = Duration::from_millis(100);
+
+// a status can take a while even for the daemon, but one that never answers shouldn't leave the
+// status thread stuck on it
+const STATUS_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub fn socket_path() -> Option<PathBuf> {
+    env::var_os("XDG_RUNTIME_DIR").map(|dir| PathBuf::from(dir).join("clanker.sock"))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn serve(_path: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "the daemon relies on inotify, which is only available on Linux",
+    ))
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TreeStatus {
+    Dirty(bool),
+    Summary(StatusSummary),
+}
+
+impl TreeStatus {
+    fn encode(&self) -> Vec<u8> {
+        match self {
+            TreeStatus::Dirty(is_dirty) => format!("dirty {}", *is_dirty as u8),
+            TreeStatus::Summary(s) => format!(
+                "summary {} {} {} {} {} {}",
+                s.staged, s.modified, s.deleted, s.renamed, s.untracked, s.conflicted
+            ),
+        }
+        .into_bytes()
+    }
+
+    fn decode(encoded: &[u8]) -> Option<TreeStatus> {
+        let mut fields = str::from_utf8(encoded).ok()?.split(' ');
+
+        match fields.next()? {
+            "dirty" => match fields.next()? {
+                "0" => Some(TreeStatus::Dirty(false)),
+                "1" => Some(TreeStatus::Dirty(true)),
+                _ => None,
+            },
+            "summary" => {
+                let mut next = || fields.next()?.parse().ok();
+
+                Some(TreeStatus::Summary(StatusSummary {
+                    staged: next()?,
+                    modified: next()?,
+                    deleted: next()?,
+                    renamed: next()?,
+                    untracked: next()?,
+                    conflicted: next()?,
+                }))
+            }
+            _ => None,
+        }
+    }
+}
+
+// computed the same way whether or not a daemon is caching it
+pub fn tree_status(
+    repo: &Repository,
+    untracked: Untracked,
+    dirty_only: bool,
+) -> Option<TreeStatus> {
+    // a freshly opened repository reads its index from disk on first use, so a
+    // refresh has nothing to pick up
+    let mut options = StatusOptions::new();
+    options.untracked(untracked).refresh(false);
+
+    if dirty_only {
+        repo.is_dirty(&options).map(TreeStatus::Dirty)
+    } else {
+        options.renames(true);
+
+        repo.status(&options).map(TreeStatus::Summary)
+    }
+}
+
+pub struct Client {
+    reader: BufReader<UnixStream>,
+}
+
+impl Client {
+    pub fn connect() -> Option<Client> {
+        let stream = UnixStream::connect(socket_path()?).ok()?;
+
+        Some(Client {
+            reader: BufReader::new(stream),
+        })
+    }
+
+    pub fn abbreviate(&mut self, dir: &Path, component: &OsStr) -> Option<String> {
+        let response = self.request(
+            &[
+                b"abbreviate",
+                dir.as_os_str().as_bytes(),
+                component.as_bytes(),
+            ],
+            Some(ABBREVIATE_TIMEOUT),
+        )?;
+
+        String::from_utf8(response).ok()
+    }
+
+    pub fn tree_status(
+        &mut self,
+        workdir: &Path,
+        untracked: Untracked,
+        dirty_only: bool,
+    ) -> Option<TreeStatus> {
+        let dirty_only: &[u8] = if dirty_only { b"1" } else { b"0" };
+
+        let response = self.request(
+            &[
+                b"status",
+                workdir.as_os_str().as_bytes(),
+                untracked.as_str().as_bytes(),
+                dirty_only,
+            ],
+            Some(STATUS_TIMEOUT),
+        )?;
+
+        TreeStatus::decode(&response)
+    }
+
+    fn request(&mut self, fields: &[&[u8]], timeout: Option<Duration>) -> Option<Vec<u8>> {
+        let mut request = Vec::new();
+
+        for field in fields {
+            request.extend_from_slice(field);
+            request.push(0);
+        }
+
+        let stream = self.reader.get_mut();
+        stream.set_read_timeout(timeout).ok()?;
+        stream.write_all(&request).ok()?;
+
+        let response = read_field(&mut self.reader).ok()??;
+
+        if response.is_empty() {
+            None
+        } else {
+            Some(response)
+        }
+    }
+}
+
+// Ok(None) if the other end hung up
+fn read_field<R: BufRead>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut field = Vec::new();
+    reader.read_until(0, &mut field)?;
+
+    if field.pop() == Some(0) {
+        Ok(Some(field))
+    } else {
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tree_status_round_trip() {
+        let statuses = [
+            TreeStatus::Dirty(false),
+            TreeStatus::Dirty(true),
+            TreeStatus::Summary(StatusSummary {
+                staged: 1,
+                modified: 2,
+                deleted: 3,
+                renamed: 4,
+                untracked: 5,
+                conflicted: 6,
+            }),
+        ];
+
+        for status in statuses.iter() {
+            assert_eq!(TreeStatus::decode(&status.encode()).as_ref(), Some(status));
+        }
+
+        assert_eq!(TreeStatus::decode(b""), None);
+        assert_eq!(TreeStatus::decode(b"summary 1 2 3"), None);
+    }
+}
@@ -0,0 +1,459 @@
+// Copyright (C) 2020 Gregory Meyer
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::{CString, OsStr, OsString},
+    fs,
+    io::{self, BufReader, ErrorKind, Write},
+    mem,
+    os::unix::{
+        ffi::{OsStrExt, OsStringExt},
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    ptr, str,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use libc::{c_int, c_void};
+
+use super::{read_field, tree_status, TreeStatus};
+use crate::{
+    compress,
+    git::{Repository, Untracked},
+};
+
+// directory listings change on these...
+const STRUCTURE_MASK: u32 = libc::IN_CREATE
+    | libc::IN_DELETE
+    | libc::IN_MOVED_FROM
+    | libc::IN_MOVED_TO
+    | libc::IN_DELETE_SELF
+    | libc::IN_MOVE_SELF
+    | libc::IN_IGNORED;
+
+// ...and working tree status also changes on these
+const WATCH_MASK: u32 = STRUCTURE_MASK | libc::IN_MODIFY | libc::IN_ATTRIB | libc::IN_CLOSE_WRITE;
+
+pub fn serve(path: &Path) -> io::Result<()> {
+    let listener = bind(path)?;
+
+    let inotify = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+
+    if inotify < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let cache = Arc::new(Mutex::new(Cache::new(inotify)));
+
+    {
+        let cache = cache.clone();
+        thread::spawn(move || invalidate(inotify, &cache));
+    }
+
+    for maybe_stream in listener.incoming() {
+        let stream = match maybe_stream {
+            Ok(s) => s,
+            Err(_) => continue,
+        };
+
+        let cache = cache.clone();
+        thread::spawn(move || handle(stream, &cache));
+    }
+
+    Ok(())
+}
+
+fn bind(path: &Path) -> io::Result<UnixListener> {
+    match UnixListener::bind(path) {
+        Err(e) if e.kind() == ErrorKind::AddrInUse => {
+            if UnixStream::connect(path).is_ok() {
+                return Err(io::Error::new(
+                    ErrorKind::AddrInUse,
+                    format!("a daemon is already listening on {}", path.display()),
+                ));
+            }
+
+            // left behind by a daemon that was killed
+            fs::remove_file(path)?;
+
+            UnixListener::bind(path)
+        }
+        r => r,
+    }
+}
+
+fn handle(stream: UnixStream, cache: &Mutex<Cache>) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+
+    while let Some(command) = read_field(&mut reader)? {
+        let response = match command.as_slice() {
+            b"abbreviate" => {
+                let dir = match read_field(&mut reader)? {
+                    Some(d) => PathBuf::from(OsString::from_vec(d)),
+                    None => break,
+                };
+                let component = match read_field(&mut reader)? {
+                    Some(c) => OsString::from_vec(c),
+                    None => break,
+                };
+
+                abbreviate(cache, &dir, &component).into_bytes()
+            }
+            b"status" => {
+                let workdir = match read_field(&mut reader)? {
+                    Some(w) => PathBuf::from(OsString::from_vec(w)),
+                    None => break,
+                };
+                let untracked = match read_field(&mut reader)? {
+                    Some(u) => str::from_utf8(&u).ok().and_then(|u| u.parse().ok()),
+                    None => break,
+                };
+                let dirty_only = match read_field(&mut reader)? {
+                    Some(d) => d == b"1",
+                    None => break,
+                };
+
+                untracked
+                    .and_then(|untracked| {
+                        status(
+                            cache,
+                            StatusKey {
+                                workdir,
+                                untracked,
+                                dirty_only,
+                            },
+                        )
+                    })
+                    .map(|s| s.encode())
+                    .unwrap_or_default()
+            }
+            _ => break,
+        };
+
+        writer.write_all(&response)?;
+        writer.write_all(b"\0")?;
+    }
+
+    Ok(())
+}
+
+fn abbreviate(cache: &Mutex<Cache>, dir: &Path, component: &OsStr) -> String {
+    let (wd, generation) = {
+        let mut cache = cache.lock().unwrap();
+
+        match cache.watch(dir) {
+            Some(wd) => {
+                if let Some(abbreviation) =
+                    cache.abbreviations.get(&wd).and_then(|a| a.get(component))
+                {
+                    return abbreviation.clone();
+                }
+
+                (wd, cache.generation)
+            }
+            // if we can't watch it, we can't cache it
+            None => return compress::abbreviate(dir, component),
+        }
+    };
+
+    let abbreviation = compress::abbreviate(dir, component);
+
+    let mut cache = cache.lock().unwrap();
+
+    if !cache.changed_since(&wd, generation) {
+        cache
+            .abbreviations
+            .entry(wd)
+            .or_default()
+            .insert(component.to_os_string(), abbreviation.clone());
+    }
+
+    abbreviation
+}
+
+fn status(cache: &Mutex<Cache>, key: StatusKey) -> Option<TreeStatus> {
+    let generation = {
+        let cache = cache.lock().unwrap();
+
+        if let Some(entry) = cache.statuses.get(&key) {
+            return Some(entry.status.clone());
+        }
+
+        cache.generation
+    };
+
+    let repo = Repository::open(&key.workdir)?;
+
+    // watched before the status is computed so that changes made in the meantime aren't missed
+    let dirs = watched_dirs(&repo, &key.workdir);
+    let wds: Option<HashSet<_>> = {
+        let mut cache = cache.lock().unwrap();
+
+        dirs.iter().map(|d| cache.watch(d)).collect()
+    };
+
+    let status = tree_status(&repo, key.untracked, key.dirty_only)?;
+
+    if let Some(wds) = wds {
+        let mut cache = cache.lock().unwrap();
+
+        if !wds.iter().any(|wd| cache.changed_since(wd, generation)) {
+            cache.statuses.insert(
+                key,
+                StatusEntry {
+                    wds,
+                    status: status.clone(),
+                },
+            );
+        }
+    }
+
+    Some(status)
+}
+
+// every directory whose contents can change the status: the repository itself for the index
+// and HEAD, its reflogs for commits, info/ for the exclude file, and every directory in the
+// working tree that isn't ignored. the repository's own subdirectories may not exist yet, in
+// which case creating them shows up in the repository itself
+fn watched_dirs(repo: &Repository, workdir: &Path) -> Vec<PathBuf> {
+    let mut dirs = vec![repo.path().to_path_buf()];
+    dirs.extend(
+        ["logs", "info"]
+            .iter()
+            .map(|d| repo.path().join(d))
+            .filter(|d| d.is_dir()),
+    );
+    let mut stack = vec![workdir.to_path_buf()];
+
+    while let Some(dir) = stack.pop() {
+        if let Ok(entries) = dir.read_dir() {
+            for entry in entries.filter_map(Result::ok) {
+                if entry.file_name() == ".git"
+                    || !entry.file_type().map(|t| t.is_dir()).unwrap_or(false)
+                {
+                    continue;
+                }
+
+                let path = entry.path();
+
+                if let Ok(relative) = path.strip_prefix(workdir) {
+                    if !repo.is_ignored(relative) {
+                        stack.push(path);
+                    }
+                }
+            }
+        }
+
+        dirs.push(dir);
+    }
+
+    dirs
+}
+
+fn invalidate(inotify: c_int, cache: &Mutex<Cache>) {
+    let mut buf = vec![0u8; 64 * 1024];
+
+    loop {
+        let len = unsafe { libc::read(inotify, buf.as_mut_ptr() as *mut c_void, buf.len()) };
+
+        if len < 0 {
+            if io::Error::last_os_error().kind() == ErrorKind::Interrupted {
+                continue;
+            }
+
+            // without events nothing can be invalidated, so nothing can be cached either
+            cache.lock().unwrap().poisoned = true;
+
+            return;
+        }
+
+        let mut cache = cache.lock().unwrap();
+        let mut offset = 0;
+
+        while offset < len as usize {
+            let event: libc::inotify_event =
+                unsafe { ptr::read_unaligned(buf[offset..].as_ptr() as *const _) };
+
+            cache.invalidate(event.wd, event.mask);
+
+            offset += mem::size_of::<libc::inotify_event>() + event.len as usize;
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct StatusKey {
+    workdir: PathBuf,
+    untracked: Untracked,
+    dirty_only: bool,
+}
+
+struct StatusEntry {
+    wds: HashSet<c_int>,
+    status: TreeStatus,
+}
+
+struct Cache {
+    inotify: c_int,
+    poisoned: bool,
+    // bumped on every event, so results computed while a directory changed can be thrown away
+    generation: u64,
+    wds: HashMap<PathBuf, c_int>,
+    last_changed: HashMap<c_int, u64>,
+    abbreviations: HashMap<c_int, HashMap<OsString, String>>,
+    statuses: HashMap<StatusKey, StatusEntry>,
+}
+
+impl Cache {
+    fn new(inotify: c_int) -> Cache {
+        Cache {
+            inotify,
+            poisoned: false,
+            generation: 0,
+            wds: HashMap::new(),
+            last_changed: HashMap::new(),
+            abbreviations: HashMap::new(),
+            statuses: HashMap::new(),
+        }
+    }
+
+    fn watch(&mut self, dir: &Path) -> Option<c_int> {
+        if self.poisoned {
+            return None;
+        }
+
+        if let Some(&wd) = self.wds.get(dir) {
+            return Some(wd);
+        }
+
+        let c_dir = CString::new(dir.as_os_str().as_bytes()).ok()?;
+
+        // fails once we run out of watches (fs.inotify.max_user_watches)
+        let wd = unsafe {
+            libc::inotify_add_watch(self.inotify, c_dir.as_ptr(), WATCH_MASK | libc::IN_ONLYDIR)
+        };
+
+        if wd < 0 {
+            return None;
+        }
+
+        self.wds.insert(dir.to_path_buf(), wd);
+
+        Some(wd)
+    }
+
+    fn changed_since(&self, wd: &c_int, generation: u64) -> bool {
+        self.poisoned
+            || self
+                .last_changed
+                .get(wd)
+                .map(|&g| g > generation)
+                .unwrap_or(false)
+    }
+
+    fn invalidate(&mut self, wd: c_int, mask: u32) {
+        self.generation += 1;
+
+        // events were dropped, so we have no idea what changed
+        if mask & libc::IN_Q_OVERFLOW != 0 {
+            for &wd in self.wds.values() {
+                self.last_changed.insert(wd, self.generation);
+            }
+
+            self.abbreviations.clear();
+            self.statuses.clear();
+
+            return;
+        }
+
+        self.last_changed.insert(wd, self.generation);
+
+        if mask & STRUCTURE_MASK != 0 {
+            self.abbreviations.remove(&wd);
+        }
+
+        self.statuses.retain(|_, entry| !entry.wds.contains(&wd));
+
+        // the watch was removed, either explicitly or because the directory is gone
+        if mask & libc::IN_IGNORED != 0 {
+            self.wds.retain(|_, &mut w| w != wd);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{
+        env, process,
+        time::{Duration, Instant},
+    };
+
+    #[test]
+    fn status_invalidated_by_exclude() {
+        let path = env::temp_dir().join(format!("clanker-daemon-{}", process::id()));
+        let _ = fs::remove_dir_all(&path);
+
+        let c_path = CString::new(path.as_os_str().as_bytes()).unwrap();
+        let mut raw = ptr::null_mut();
+
+        unsafe {
+            libgit2_sys::git_libgit2_init();
+            assert_eq!(
+                libgit2_sys::git_repository_init(&mut raw, c_path.as_ptr(), 0),
+                0
+            );
+            libgit2_sys::git_repository_free(raw);
+            libgit2_sys::git_libgit2_shutdown();
+        }
+
+        let inotify = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+        assert!(inotify >= 0);
+
+        let cache = Arc::new(Mutex::new(Cache::new(inotify)));
+
+        {
+            let cache = cache.clone();
+            thread::spawn(move || invalidate(inotify, &cache));
+        }
+
+        let key = StatusKey {
+            workdir: path.clone(),
+            untracked: Untracked::Normal,
+            dirty_only: true,
+        };
+
+        fs::write(path.join("a"), "").unwrap();
+        assert_eq!(status(&cache, key.clone()), Some(TreeStatus::Dirty(true)));
+
+        fs::create_dir_all(path.join(".git/info")).unwrap();
+        fs::write(path.join(".git/info/exclude"), "a\n").unwrap();
+
+        // events arrive asynchronously, so give the cache a moment to catch up
+        let start = Instant::now();
+
+        while status(&cache, key.clone()) != Some(TreeStatus::Dirty(false)) {
+            assert!(start.elapsed() < Duration::from_secs(5), "stale status");
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let _ = fs::remove_dir_all(&path);
+    }
+}
@@ -13,36 +13,156 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use std::{ffi::CStr, marker::PhantomData, mem::MaybeUninit, ptr, slice, str};
+use std::{
+    env,
+    ffi::{CStr, CString, OsStr},
+    fs,
+    io::Read,
+    marker::PhantomData,
+    mem::MaybeUninit,
+    os::unix::{ffi::OsStrExt, fs::MetadataExt},
+    path::{Path, PathBuf},
+    ptr, slice,
+    str::{self, FromStr},
+};
 
-use libc::{c_char, c_int, c_void};
+use libc::{c_char, c_int, c_uint, c_void};
 use libgit2_sys::{
-    git_buf, git_commit, git_object, git_oid, git_reference, git_repository, GIT_OBJECT_ANY,
-    GIT_OBJECT_COMMIT, GIT_REPOSITORY_OPEN_FROM_ENV,
+    git_buf, git_commit, git_config, git_config_entry, git_describe_format_options,
+    git_describe_options, git_index, git_index_entry, git_object, git_oid, git_reference,
+    git_repository, git_repository_state_t, git_status_options, git_submodule, git_worktree,
+    GIT_ATTR_CHECK_FILE_THEN_INDEX, GIT_ATTR_CHECK_NO_SYSTEM, GIT_DESCRIBE_TAGS,
+    GIT_INDEX_ENTRY_SKIP_WORKTREE, GIT_OBJECT_ANY, GIT_OBJECT_COMMIT, GIT_REPOSITORY_OPEN_FROM_ENV,
+    GIT_REPOSITORY_STATE_APPLY_MAILBOX, GIT_REPOSITORY_STATE_APPLY_MAILBOX_OR_REBASE,
+    GIT_REPOSITORY_STATE_BISECT, GIT_REPOSITORY_STATE_CHERRYPICK,
+    GIT_REPOSITORY_STATE_CHERRYPICK_SEQUENCE, GIT_REPOSITORY_STATE_MERGE,
+    GIT_REPOSITORY_STATE_REBASE, GIT_REPOSITORY_STATE_REBASE_INTERACTIVE,
+    GIT_REPOSITORY_STATE_REBASE_MERGE, GIT_REPOSITORY_STATE_REVERT,
+    GIT_REPOSITORY_STATE_REVERT_SEQUENCE, GIT_STATUS_CONFLICTED, GIT_STATUS_INDEX_DELETED,
+    GIT_STATUS_INDEX_MODIFIED, GIT_STATUS_INDEX_NEW, GIT_STATUS_INDEX_RENAMED,
+    GIT_STATUS_INDEX_TYPECHANGE, GIT_STATUS_OPTIONS_VERSION, GIT_STATUS_OPT_INCLUDE_UNTRACKED,
+    GIT_STATUS_OPT_NO_REFRESH, GIT_STATUS_OPT_RECURSE_UNTRACKED_DIRS,
+    GIT_STATUS_OPT_RENAMES_HEAD_TO_INDEX, GIT_STATUS_OPT_RENAMES_INDEX_TO_WORKDIR,
+    GIT_STATUS_SHOW_INDEX_AND_WORKDIR, GIT_STATUS_WT_DELETED, GIT_STATUS_WT_MODIFIED,
+    GIT_STATUS_WT_NEW, GIT_STATUS_WT_RENAMED, GIT_STATUS_WT_TYPECHANGE,
+    GIT_SUBMODULE_IGNORE_UNSPECIFIED, GIT_SUBMODULE_STATUS_WD_INDEX_MODIFIED,
+    GIT_SUBMODULE_STATUS_WD_MODIFIED, GIT_SUBMODULE_STATUS_WD_UNTRACKED,
+    GIT_SUBMODULE_STATUS_WD_WD_MODIFIED,
 };
 
+// not bound by libgit2-sys, but linked in all the same
+extern "C" {
+    fn git_config_get_multivar_foreach(
+        cfg: *const git_config,
+        name: *const c_char,
+        regexp: *const c_char,
+        callback: extern "C" fn(*const git_config_entry, *mut c_void) -> c_int,
+        payload: *mut c_void,
+    ) -> c_int;
+
+    fn git_config_foreach_match(
+        cfg: *const git_config,
+        regexp: *const c_char,
+        callback: extern "C" fn(*const git_config_entry, *mut c_void) -> c_int,
+        payload: *mut c_void,
+    ) -> c_int;
+
+    fn git_reference_owner(reference: *const git_reference) -> *mut git_repository;
+
+    fn git_repository_commondir(repo: *const git_repository) -> *const c_char;
+
+    fn git_repository_head_unborn(repo: *mut git_repository) -> c_int;
+
+    fn git_status_foreach_ext(
+        repo: *mut git_repository,
+        opts: *const git_status_options,
+        callback: extern "C" fn(*const c_char, c_uint, *mut c_void) -> c_int,
+        payload: *mut c_void,
+    ) -> c_int;
+
+    fn git_worktree_open_from_repository(
+        out: *mut *mut git_worktree,
+        repo: *mut git_repository,
+    ) -> c_int;
+
+    fn git_worktree_name(wt: *const git_worktree) -> *const c_char;
+
+    fn git_worktree_free(wt: *mut git_worktree);
+}
+
 pub struct Repository(*mut git_repository);
 
 impl Repository {
-    pub fn open_from_env() -> Option<Repository> {
-        unsafe { libgit2_sys::git_libgit2_init() };
+    // like git, refuses repositories owned by someone else unless they're listed in
+    // safe.directory. libgit2 doesn't check this itself until 1.4
+    pub fn open_from_env(check_ownership: bool) -> Result<Repository, OpenError> {
+        let repo = with_libgit2(|| {
+            let mut repo = MaybeUninit::uninit();
 
-        let mut repo = MaybeUninit::uninit();
+            match unsafe {
+                libgit2_sys::git_repository_open_ext(
+                    &mut *repo.as_mut_ptr(),
+                    ptr::null(),
+                    GIT_REPOSITORY_OPEN_FROM_ENV,
+                    ptr::null(),
+                )
+            } {
+                0 => Some(Repository(unsafe { repo.assume_init() })),
+                _ => None,
+            }
+        })
+        .ok_or(OpenError::NotFound)?;
 
-        match unsafe {
-            libgit2_sys::git_repository_open_ext(
-                &mut *repo.as_mut_ptr(),
-                ptr::null(),
-                GIT_REPOSITORY_OPEN_FROM_ENV,
-                ptr::null(),
-            )
-        } {
-            0 => Some(Repository(unsafe { repo.assume_init() })),
-            _ => None,
+        if check_ownership && !is_trusted(repo.workdir(), repo.path()) {
+            return Err(OpenError::DubiousOwnership);
         }
+
+        Ok(repo)
+    }
+
+    pub fn open(path: &Path) -> Option<Repository> {
+        let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+
+        with_libgit2(|| {
+            let mut repo = MaybeUninit::uninit();
+
+            match unsafe {
+                libgit2_sys::git_repository_open(&mut *repo.as_mut_ptr(), path.as_ptr())
+            } {
+                0 => Some(Repository(unsafe { repo.assume_init() })),
+                _ => None,
+            }
+        })
+    }
+
+    // the repository containing path, searching upwards like git does
+    pub fn discover(path: &Path) -> Option<Repository> {
+        let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+
+        with_libgit2(|| {
+            let mut repo = MaybeUninit::uninit();
+
+            match unsafe {
+                libgit2_sys::git_repository_open_ext(
+                    &mut *repo.as_mut_ptr(),
+                    path.as_ptr(),
+                    0,
+                    ptr::null(),
+                )
+            } {
+                0 => Some(Repository(unsafe { repo.assume_init() })),
+                _ => None,
+            }
+        })
+    }
+
+    // the closest repository enclosing this one's working directory, which may or may not
+    // know about this one as a submodule
+    pub fn enclosing(&self) -> Option<Repository> {
+        Repository::discover(self.workdir()?.parent()?)
     }
 
-    pub fn head(&self) -> Option<Reference> {
+    pub fn head(&self) -> Option<Reference<'_>> {
         let mut head = MaybeUninit::uninit();
 
         match unsafe { libgit2_sys::git_repository_head(&mut *head.as_mut_ptr(), self.0) } {
@@ -51,89 +171,1326 @@ impl Repository {
         }
     }
 
-    pub fn lookup_object(&self, oid: Oid) -> Option<Object> {
-        let mut obj = MaybeUninit::uninit();
+    // true when HEAD points to a branch with no commits yet, like in a new repository
+    pub fn is_head_unborn(&self) -> bool {
+        unsafe { git_repository_head_unborn(self.0) == 1 }
+    }
+
+    // the branch HEAD points to when it has no commits yet
+    pub fn unborn_branch_name(&self) -> Option<String> {
+        if !self.is_head_unborn() {
+            return None;
+        }
+
+        let head = self.find_reference("HEAD")?;
+        let target = head.symbolic_target()?.to_string_lossy();
+
+        Some(
+            target
+                .strip_prefix("refs/heads/")
+                .unwrap_or(&target)
+                .to_string(),
+        )
+    }
+
+    pub fn is_bare(&self) -> bool {
+        unsafe { libgit2_sys::git_repository_is_bare(self.0) != 0 }
+    }
+
+    pub fn find_reference(&self, name: &str) -> Option<Reference<'_>> {
+        let name = CString::new(name).ok()?;
+        let mut reference = MaybeUninit::uninit();
 
         match unsafe {
-            libgit2_sys::git_object_lookup(&mut *obj.as_mut_ptr(), self.0, oid.0, GIT_OBJECT_ANY)
+            libgit2_sys::git_reference_lookup(&mut *reference.as_mut_ptr(), self.0, name.as_ptr())
         } {
-            0 => Some(Object(unsafe { obj.assume_init() }, PhantomData)),
+            0 => Some(Reference(unsafe { reference.assume_init() }, PhantomData)),
             _ => None,
         }
     }
-}
 
-impl<'a> Repository {
-    pub fn tags_pointing_to(&'a self, commit: &'a Commit) -> Option<Vec<&'a CStr>> {
-        let mut payload = Payload {
-            repo: self,
-            commit,
-            result: Vec::new(),
+    pub fn path(&self) -> &Path {
+        let path = unsafe { CStr::from_ptr(libgit2_sys::git_repository_path(self.0)) };
+
+        OsStr::from_bytes(path.to_bytes()).as_ref()
+    }
+
+    // None for bare repositories
+    pub fn workdir(&self) -> Option<&Path> {
+        let path = unsafe { libgit2_sys::git_repository_workdir(self.0) };
+
+        if path.is_null() {
+            None
+        } else {
+            let path = unsafe { CStr::from_ptr(path) };
+
+            Some(OsStr::from_bytes(path.to_bytes()).as_ref())
+        }
+    }
+
+    // path is relative to the working directory
+    pub fn is_ignored(&self, path: &Path) -> bool {
+        let path = match CString::new(path.as_os_str().as_bytes()) {
+            Ok(p) => p,
+            Err(_) => return false,
         };
-        let payload_ptr = &mut payload as *mut _ as *mut c_void;
+        let mut ignored = 0;
+
+        match unsafe { libgit2_sys::git_status_should_ignore(&mut ignored, self.0, path.as_ptr()) }
+        {
+            0 => ignored != 0,
+            _ => false,
+        }
+    }
+
+    // where refs live; differs from path() in linked worktrees
+    pub fn commondir(&self) -> &Path {
+        let path = unsafe { CStr::from_ptr(git_repository_commondir(self.0)) };
+
+        OsStr::from_bytes(path.to_bytes()).as_ref()
+    }
+
+    // true in linked worktrees, but not in the main one
+    pub fn is_worktree(&self) -> bool {
+        unsafe { libgit2_sys::git_repository_is_worktree(self.0) != 0 }
+    }
+
+    // None unless this is a linked worktree
+    pub fn worktree_name(&self) -> Option<String> {
+        let mut worktree = MaybeUninit::uninit();
+
+        if unsafe { git_worktree_open_from_repository(&mut *worktree.as_mut_ptr(), self.0) } != 0 {
+            return None;
+        }
+
+        let worktree = unsafe { worktree.assume_init() };
+        let name = unsafe { CStr::from_ptr(git_worktree_name(worktree)) }
+            .to_string_lossy()
+            .into_owned();
+        unsafe { git_worktree_free(worktree) };
+
+        Some(name)
+    }
+
+    // this repository's configuration, layered over the global and system files
+    pub fn config(&self) -> Option<Config> {
+        with_libgit2(|| {
+            let mut config = MaybeUninit::uninit();
+
+            match unsafe { libgit2_sys::git_repository_config(&mut *config.as_mut_ptr(), self.0) } {
+                0 => Some(Config(unsafe { config.assume_init() })),
+                _ => None,
+            }
+        })
+    }
+
+    fn index(&self) -> Option<Index> {
+        let mut index = MaybeUninit::uninit();
+
+        match unsafe { libgit2_sys::git_repository_index(&mut *index.as_mut_ptr(), self.0) } {
+            0 => Some(Index(unsafe { index.assume_init() })),
+            _ => None,
+        }
+    }
+
+    // git ignores the patterns unless core.sparseCheckout is set, and vice versa. config is
+    // this repository's, from Repository::config
+    pub fn is_sparse(&self, config: &Config) -> bool {
+        // git sparse-checkout writes to config.worktree, which libgit2 doesn't read itself
+        let worktree_config = if config.get_bool("extensions.worktreeConfig") == Some(true) {
+            Config::open(&self.path().join("config.worktree"))
+        } else {
+            None
+        };
+
+        let enabled = worktree_config
+            .and_then(|c| c.get_bool("core.sparseCheckout"))
+            .or_else(|| config.get_bool("core.sparseCheckout"))
+            .unwrap_or(false);
+
+        enabled && self.path().join("info").join("sparse-checkout").is_file()
+    }
+
+    // only some of the history was fetched
+    pub fn is_shallow(&self) -> bool {
+        unsafe { libgit2_sys::git_repository_is_shallow(self.0) != 0 }
+    }
+
+    // objects left out by a --filter clone are fetched from a promisor remote when needed
+    pub fn is_partial_clone(&self, config: &Config) -> bool {
+        has_promisor(config)
+    }
+
+    // None unless some file in the index has the lfs filter. whether the others are pointers is
+    // judged from a sample of them, so large checkouts don't have to be read in full
+    pub fn lfs(&self, config: &Config) -> Option<Lfs> {
+        const MAX_SAMPLES: usize = 64;
+
+        let workdir = self.workdir()?;
+        let index = self.index()?;
+
+        // looking up every file's attributes is slow, so only files that the lfs patterns
+        // could match are looked up
+        let patterns = self.lfs_patterns(&index, workdir, config);
+
+        if patterns.is_empty() {
+            return None;
+        }
+
+        let mut is_tracked = false;
+        let mut num_samples = 0;
+
+        for entry in index.entries() {
+            let path = unsafe { CStr::from_ptr(entry.path) }.to_bytes();
+
+            if !patterns.iter().any(|p| p.may_match(path)) || !self.has_lfs_filter(entry.path) {
+                continue;
+            }
+
+            is_tracked = true;
+
+            // files outside of a sparse checkout aren't there to be smudged
+            if entry.flags_extended & GIT_INDEX_ENTRY_SKIP_WORKTREE as u16 != 0 {
+                continue;
+            }
+
+            match is_lfs_pointer(&workdir.join(OsStr::from_bytes(path))) {
+                Some(true) => return Some(Lfs::Unsmudged),
+                Some(false) => num_samples += 1,
+                None => (), // deleted, most likely
+            }
+
+            if num_samples == MAX_SAMPLES {
+                break;
+            }
+        }
+
+        if is_tracked {
+            Some(Lfs::Smudged)
+        } else {
+            None
+        }
+    }
+
+    // patterns given the lfs filter by .gitattributes files in the index, info/attributes or
+    // core.attributesFile. the system attributes file is left out
+    fn lfs_patterns(&self, index: &Index, workdir: &Path, config: &Config) -> Vec<LfsPattern> {
+        const FILENAME: &[u8] = b".gitattributes";
+
+        let mut patterns = Vec::new();
+
+        for entry in index.entries() {
+            let path = unsafe { CStr::from_ptr(entry.path) }.to_bytes();
+
+            if !path.ends_with(FILENAME) {
+                continue;
+            }
+
+            let dir = &path[..path.len() - FILENAME.len()];
+
+            if !dir.is_empty() && !dir.ends_with(b"/") {
+                continue;
+            }
+
+            match fs::read(workdir.join(OsStr::from_bytes(path))) {
+                Ok(contents) => parse_lfs_patterns(&contents, dir, &mut patterns),
+                // libgit2 falls back to the index's copy, so anything under dir might match
+                Err(_) => patterns.push(LfsPattern {
+                    dir: dir.to_vec(),
+                    suffix: None,
+                }),
+            }
+        }
+
+        let global = config
+            .get_multivar("core.attributesFile")
+            .pop()
+            .map(|f| expand_tilde(&f))
+            .or_else(|| {
+                env::var_os("XDG_CONFIG_HOME")
+                    .map(PathBuf::from)
+                    .or_else(|| dirs::home_dir().map(|h| h.join(".config")))
+                    .map(|c| c.join("git/attributes"))
+            });
+
+        for path in [Some(self.commondir().join("info/attributes")), global]
+            .iter()
+            .flatten()
+        {
+            if let Ok(contents) = fs::read(path) {
+                parse_lfs_patterns(&contents, b"", &mut patterns);
+            }
+        }
+
+        patterns
+    }
+
+    fn has_lfs_filter(&self, path: *const c_char) -> bool {
+        let mut value = ptr::null();
 
         if unsafe {
-            libgit2_sys::git_tag_foreach(self.0, Some(Repository::tag_cb_entry), payload_ptr)
+            libgit2_sys::git_attr_get(
+                &mut value,
+                self.0,
+                GIT_ATTR_CHECK_FILE_THEN_INDEX | GIT_ATTR_CHECK_NO_SYSTEM,
+                path,
+                b"filter\0".as_ptr() as *const c_char,
+            )
         } != 0
+            || value.is_null()
         {
-            None
+            return false;
+        }
+
+        unsafe { CStr::from_ptr(value) }.to_bytes() == b"lfs"
+    }
+
+    pub fn state(&self) -> State {
+        match unsafe { libgit2_sys::git_repository_state(self.0) } as git_repository_state_t {
+            GIT_REPOSITORY_STATE_MERGE => State::Merge,
+            GIT_REPOSITORY_STATE_REVERT | GIT_REPOSITORY_STATE_REVERT_SEQUENCE => State::Revert,
+            GIT_REPOSITORY_STATE_CHERRYPICK | GIT_REPOSITORY_STATE_CHERRYPICK_SEQUENCE => {
+                State::CherryPick
+            }
+            GIT_REPOSITORY_STATE_BISECT => State::Bisect,
+            GIT_REPOSITORY_STATE_REBASE => State::Rebase,
+            GIT_REPOSITORY_STATE_REBASE_INTERACTIVE => State::RebaseInteractive,
+            GIT_REPOSITORY_STATE_REBASE_MERGE => State::RebaseMerge,
+            GIT_REPOSITORY_STATE_APPLY_MAILBOX => State::ApplyMailbox,
+            GIT_REPOSITORY_STATE_APPLY_MAILBOX_OR_REBASE => State::ApplyMailboxOrRebase,
+            _ => State::Clean,
+        }
+    }
+
+    // libgit2 knows a rebase is happening, but not how far along it is
+    pub fn rebase_progress(&self) -> Option<RebaseProgress> {
+        let merge_dir = self.path().join("rebase-merge");
+        let apply_dir = self.path().join("rebase-apply");
+
+        let (dir, step_filename, total_filename) = if merge_dir.is_dir() {
+            (merge_dir, "msgnum", "end")
+        } else if apply_dir.is_dir() {
+            (apply_dir, "next", "last")
         } else {
-            Some(payload.result)
+            return None;
+        };
+
+        let read = |filename| fs::read_to_string(dir.join(filename)).ok();
+
+        let step = read(step_filename)?.trim().parse().ok()?;
+        let total = read(total_filename)?.trim().parse().ok()?;
+
+        // "detached HEAD" if the rebase didn't start from a branch
+        let branch_name = read("head-name")
+            .and_then(|n| n.trim().strip_prefix("refs/heads/").map(str::to_string));
+
+        Some(RebaseProgress {
+            step,
+            total,
+            branch_name,
+        })
+    }
+
+    // path is relative to the working directory
+    pub fn find_submodule(&self, path: &Path) -> Option<Submodule<'_>> {
+        let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+        let mut submodule = MaybeUninit::uninit();
+
+        match unsafe {
+            libgit2_sys::git_submodule_lookup(&mut *submodule.as_mut_ptr(), self.0, path.as_ptr())
+        } {
+            0 => Some(Submodule(unsafe { submodule.assume_init() }, PhantomData)),
+            _ => None,
         }
     }
 
-    fn tag_cb(&'a self, commit: &'a Commit, name: &'a CStr, oid: Oid, result: &mut Vec<&'a CStr>) {
-        if let Some(obj) = self.lookup_object(oid) {
-            if let Some(target_commit) = obj.peel_to_commit() {
-                if commit.id() == target_commit.id() {
-                    result.push(name);
-                }
+    // submodules with new commits, changes or untracked files in their working directories
+    pub fn dirty_submodule_count(&self) -> Option<usize> {
+        const DIRTY_MASK: u32 = GIT_SUBMODULE_STATUS_WD_MODIFIED
+            | GIT_SUBMODULE_STATUS_WD_INDEX_MODIFIED
+            | GIT_SUBMODULE_STATUS_WD_WD_MODIFIED
+            | GIT_SUBMODULE_STATUS_WD_UNTRACKED;
+
+        let mut names: Vec<CString> = Vec::new();
+        let payload_ptr = &mut names as *mut Vec<CString> as *mut c_void;
+
+        if unsafe {
+            libgit2_sys::git_submodule_foreach(
+                self.0,
+                Some(Repository::submodule_cb_entry),
+                payload_ptr,
+            )
+        } != 0
+        {
+            return None;
+        }
+
+        let mut count = 0;
+
+        for name in names.iter() {
+            let mut status = 0;
+
+            if unsafe {
+                libgit2_sys::git_submodule_status(
+                    &mut status,
+                    self.0,
+                    name.as_ptr(),
+                    GIT_SUBMODULE_IGNORE_UNSPECIFIED,
+                )
+            } != 0
+            {
+                return None;
+            }
+
+            if status & DIRTY_MASK != 0 {
+                count += 1;
             }
         }
+
+        Some(count)
     }
 
-    extern "C" fn tag_cb_entry(
+    extern "C" fn submodule_cb_entry(
+        _submodule: *mut git_submodule,
         name: *const c_char,
-        oid: *mut git_oid,
         payload: *mut c_void,
     ) -> c_int {
-        const OFFSET: isize = 10; // "refs/tags/".len()
+        let names = unsafe { &mut *(payload as *mut Vec<CString>) };
+        names.push(unsafe { CStr::from_ptr(name) }.to_owned());
 
-        let name = unsafe { CStr::from_ptr(name.offset(OFFSET)) };
-        let payload = unsafe { &mut *(payload as *mut Payload) };
+        0
+    }
 
-        let oid = Oid(oid, PhantomData);
-        payload
-            .repo
-            .tag_cb(payload.commit, name, oid, &mut payload.result);
+    pub fn lookup_object(&self, oid: Oid) -> Option<Object<'_>> {
+        let mut obj = MaybeUninit::uninit();
+
+        match unsafe {
+            libgit2_sys::git_object_lookup(&mut *obj.as_mut_ptr(), self.0, oid.0, GIT_OBJECT_ANY)
+        } {
+            0 => Some(Object(unsafe { obj.assume_init() }, PhantomData)),
+            _ => None,
+        }
+    }
+
+    pub fn ahead_behind(&self, local: Oid, upstream: Oid) -> Option<(usize, usize)> {
+        let mut ahead = 0;
+        let mut behind = 0;
+
+        match unsafe {
+            libgit2_sys::git_graph_ahead_behind(
+                &mut ahead,
+                &mut behind,
+                self.0,
+                local.0,
+                upstream.0,
+            )
+        } {
+            0 => Some((ahead, behind)),
+            _ => None,
+        }
+    }
+
+    pub fn stash_count(&self) -> Option<usize> {
+        let mut count = 0;
+        let payload_ptr = &mut count as *mut usize as *mut c_void;
+
+        match unsafe {
+            libgit2_sys::git_stash_foreach(self.0, Some(Repository::stash_cb_entry), payload_ptr)
+        } {
+            0 => Some(count),
+            _ => None,
+        }
+    }
+
+    extern "C" fn stash_cb_entry(
+        _index: usize,
+        _message: *const c_char,
+        _stash_id: *const git_oid,
+        payload: *mut c_void,
+    ) -> c_int {
+        let count = unsafe { &mut *(payload as *mut usize) };
+        *count += 1;
 
         0
     }
-}
 
-impl Drop for Repository {
-    fn drop(&mut self) {
-        unsafe { libgit2_sys::git_repository_free(self.0) };
-        unsafe { libgit2_sys::git_libgit2_shutdown() };
+    pub fn is_dirty(&self, options: &StatusOptions) -> Option<bool> {
+        let mut is_dirty = false;
+        let payload_ptr = &mut is_dirty as *mut bool as *mut c_void;
+
+        match unsafe {
+            git_status_foreach_ext(self.0, &options.0, Repository::dirty_cb_entry, payload_ptr)
+        } {
+            0 => Some(is_dirty),
+            _ if is_dirty => Some(true), // we aborted the walk ourselves
+            _ => None,
+        }
     }
-}
 
-#[repr(C)]
-struct Payload<'a> {
-    repo: &'a Repository,
-    commit: &'a Commit<'a>,
-    result: Vec<&'a CStr>,
+    extern "C" fn dirty_cb_entry(
+        _path: *const c_char,
+        _flags: c_uint,
+        payload: *mut c_void,
+    ) -> c_int {
+        let is_dirty = unsafe { &mut *(payload as *mut bool) };
+        *is_dirty = true;
+
+        1 // any entry at all means the repository is dirty, so stop here
+    }
+
+    pub fn status(&self, options: &StatusOptions) -> Option<StatusSummary> {
+        let mut summary = StatusSummary::default();
+        let payload_ptr = &mut summary as *mut StatusSummary as *mut c_void;
+
+        match unsafe {
+            git_status_foreach_ext(self.0, &options.0, Repository::status_cb_entry, payload_ptr)
+        } {
+            0 => Some(summary),
+            _ => None,
+        }
+    }
+
+    extern "C" fn status_cb_entry(
+        _path: *const c_char,
+        flags: c_uint,
+        payload: *mut c_void,
+    ) -> c_int {
+        let summary = unsafe { &mut *(payload as *mut StatusSummary) };
+        summary.add(flags);
+
+        0
+    }
 }
 
-pub struct Reference<'repo>(*mut git_reference, PhantomData<&'repo Repository>);
+impl Repository {
+    // packed-refs can record what each tag peels to, which lets us skip looking up every tag
+    // object in repositories with thousands of them. falls back to walking every tag with
+    // libgit2 if the peeled values aren't there. either way the search stops once limit tags
+    // are found. packed-refs is sorted by name, so there those are the first tags in order
+    pub fn tags_pointing_to(&self, commit: &Commit, limit: Option<usize>) -> Option<Vec<String>> {
+        let mut result = self
+            .tags_pointing_to_packed(commit, limit)
+            .or_else(|| self.tags_pointing_to_foreach(commit, limit))?;
+        result.sort();
+
+        if let Some(limit) = limit {
+            result.truncate(limit);
+        }
+
+        Some(result)
+    }
+
+    // with a limit, the result holds at least the first that many tags by name, plus any loose
+    // ones
+    fn tags_pointing_to_packed(
+        &self,
+        commit: &Commit,
+        limit: Option<usize>,
+    ) -> Option<Vec<String>> {
+        const HEADER: &[u8] = b"# pack-refs with:";
+        const PREFIX: &[u8] = b"refs/tags/";
+        const HEX_LEN: usize = 40;
+
+        let packed_refs = fs::read(self.commondir().join("packed-refs")).ok()?;
+        let mut lines = packed_refs.split(|&b| b == b'\n').peekable();
+
+        let header = lines.next()?;
+
+        if !header.starts_with(HEADER) {
+            return None;
+        }
+
+        let traits = &header[HEADER.len()..];
+        let has_trait = |name: &[u8]| traits.split(|&b| b == b' ').any(|t| t == name);
+
+        if !has_trait(b"peeled") && !has_trait(b"fully-peeled") {
+            return None;
+        }
+
+        // unsorted files can't be cut short, since a smaller name might still be to come
+        let limit = limit.filter(|_| has_trait(b"sorted"));
+
+        let target = commit.id().to_hex();
+        let mut result = Vec::new();
+
+        // loose tags shadow packed tags of the same name. there usually aren't many, so they're
+        // checked the slow way. one that doesn't resolve leaves the packed tag in place, like git
+        let mut loose_names = Vec::new();
+        collect_loose_names(
+            &self.commondir().join("refs/tags"),
+            &mut Vec::new(),
+            &mut loose_names,
+        );
+        let mut shadowed = Vec::with_capacity(loose_names.len());
+
+        for name in loose_names {
+            let mut refname = PREFIX.to_vec();
+            refname.extend_from_slice(&name);
+            refname.push(b'\0');
+
+            let mut oid = MaybeUninit::uninit();
+
+            if unsafe {
+                libgit2_sys::git_reference_name_to_id(
+                    &mut *oid.as_mut_ptr(),
+                    self.0,
+                    refname.as_ptr() as *const c_char,
+                )
+            } != 0
+            {
+                continue;
+            }
+
+            let oid = unsafe { oid.assume_init() };
+
+            if self.peels_to(Oid(&oid, PhantomData), commit) {
+                result.push(String::from_utf8_lossy(&name).into_owned());
+            }
+
+            shadowed.push(name);
+        }
+
+        let mut num_packed = 0;
+
+        while let Some(line) = lines.next() {
+            if Some(num_packed) == limit {
+                break;
+            }
+
+            // skips comments and peel lines
+            if line.len() <= HEX_LEN || line[HEX_LEN] != b' ' {
+                continue;
+            }
+
+            let name = &line[HEX_LEN + 1..];
+
+            if !name.starts_with(PREFIX) {
+                continue;
+            }
+
+            let name = &name[PREFIX.len()..];
+
+            // annotated tags are followed by the commit they peel to
+            let peeled = match lines.peek() {
+                Some(next) if next.first() == Some(&b'^') => &next[1..],
+                _ => &line[..HEX_LEN],
+            };
+
+            if peeled == &target[..] && !shadowed.iter().any(|n| n == name) {
+                result.push(String::from_utf8_lossy(name).into_owned());
+                num_packed += 1;
+            }
+        }
+
+        Some(result)
+    }
+
+    // git_tag_foreach has no order, so with a limit these are whichever tags are found first
+    fn tags_pointing_to_foreach(
+        &self,
+        commit: &Commit,
+        limit: Option<usize>,
+    ) -> Option<Vec<String>> {
+        let mut payload = Payload {
+            repo: self,
+            commit,
+            limit,
+            result: Vec::new(),
+        };
+        let payload_ptr = &mut payload as *mut _ as *mut c_void;
+
+        match unsafe {
+            libgit2_sys::git_tag_foreach(self.0, Some(Repository::tag_cb_entry), payload_ptr)
+        } {
+            0 | TAG_CB_STOP => Some(payload.result),
+            _ => None,
+        }
+    }
+
+    fn peels_to(&self, oid: Oid, commit: &Commit) -> bool {
+        self.lookup_object(oid)
+            .and_then(|obj| obj.peel_to_commit())
+            .map(|target_commit| commit.id() == target_commit.id())
+            .unwrap_or(false)
+    }
+
+    extern "C" fn tag_cb_entry(
+        name: *const c_char,
+        oid: *mut git_oid,
+        payload: *mut c_void,
+    ) -> c_int {
+        const OFFSET: isize = 10; // "refs/tags/".len()
+
+        let name = unsafe { CStr::from_ptr(name.offset(OFFSET)) };
+        let payload = unsafe { &mut *(payload as *mut Payload) };
+
+        let oid = Oid(oid, PhantomData);
+
+        if payload.repo.peels_to(oid, payload.commit) {
+            payload.result.push(name.to_string_lossy().into_owned());
+
+            if Some(payload.result.len()) == payload.limit {
+                return TAG_CB_STOP;
+            }
+        }
+
+        0
+    }
+}
+
+fn collect_loose_names(dir: &Path, prefix: &mut Vec<u8>, names: &mut Vec<Vec<u8>>) {
+    let entries = match dir.read_dir() {
+        Ok(e) => e,
+        Err(_) => return,
+    };
+
+    for entry in entries.filter_map(Result::ok) {
+        let filename = entry.file_name();
+        let prefix_len = prefix.len();
+        prefix.extend_from_slice(filename.as_bytes());
+
+        if entry.file_type().map(|t| t.is_dir()).unwrap_or(false) {
+            prefix.push(b'/');
+            collect_loose_names(&entry.path(), prefix, names);
+        } else {
+            names.push(prefix.clone());
+        }
+
+        prefix.truncate(prefix_len);
+    }
+}
+
+impl Drop for Repository {
+    fn drop(&mut self) {
+        unsafe { libgit2_sys::git_repository_free(self.0) };
+        unsafe { libgit2_sys::git_libgit2_shutdown() };
+    }
+}
+
+// a repository that libgit2 1.0 won't open because it sets repositoryformatversion = 1, like
+// partial clones do. libgit2 doesn't learn about their extensions until 1.5, but HEAD and the
+// configuration are plain files that can be read without it
+pub struct UnsupportedRepository {
+    commondir: PathBuf,
+    head: String,
+    config: Config,
+}
+
+impl UnsupportedRepository {
+    pub fn discover(dir: &Path, check_ownership: bool) -> Result<UnsupportedRepository, OpenError> {
+        let (workdir, git_dir) = find_git_dir(dir).ok_or(OpenError::NotFound)?;
+
+        // linked worktrees keep everything but HEAD in the main repository
+        let commondir = match fs::read_to_string(git_dir.join("commondir")) {
+            Ok(c) => git_dir.join(c.trim_end()),
+            Err(_) => git_dir.clone(),
+        };
+        let config = Config::open(&commondir.join("config")).ok_or(OpenError::NotFound)?;
+
+        let version = config.get_multivar("core.repositoryformatversion");
+
+        if version.last().map(String::as_str) != Some("1") {
+            return Err(OpenError::NotFound);
+        }
+
+        if check_ownership && !is_trusted(workdir.as_deref(), &git_dir) {
+            return Err(OpenError::DubiousOwnership);
+        }
+
+        let head = fs::read_to_string(git_dir.join("HEAD")).map_err(|_| OpenError::NotFound)?;
+
+        Ok(UnsupportedRepository {
+            commondir,
+            head: head.trim_end().to_string(),
+            config,
+        })
+    }
+
+    // None if HEAD is detached
+    pub fn branch_name(&self) -> Option<&str> {
+        let target = self.head.strip_prefix("ref: ")?;
+
+        Some(target.strip_prefix("refs/heads/").unwrap_or(target))
+    }
+
+    // the abbreviated commit a detached HEAD points to
+    pub fn short_id(&self) -> Option<&str> {
+        if self.head.len() >= 40 && self.head.bytes().all(|b| b.is_ascii_hexdigit()) {
+            self.head.get(..7)
+        } else {
+            None
+        }
+    }
+
+    pub fn is_shallow(&self) -> bool {
+        self.commondir.join("shallow").is_file()
+    }
+
+    pub fn is_partial_clone(&self) -> bool {
+        has_promisor(&self.config)
+    }
+}
+
+// the working directory, if any, and git directory that would be found from dir
+fn find_git_dir(dir: &Path) -> Option<(Option<PathBuf>, PathBuf)> {
+    if let Some(git_dir) = env::var_os("GIT_DIR") {
+        return Some((
+            env::var_os("GIT_WORK_TREE").map(PathBuf::from),
+            git_dir.into(),
+        ));
+    }
+
+    for ancestor in dir.ancestors() {
+        let dot_git = ancestor.join(".git");
+
+        if dot_git.is_dir() {
+            return Some((Some(ancestor.to_path_buf()), dot_git));
+        }
+
+        // submodules and linked worktrees point to their git directory with a file
+        if let Ok(contents) = fs::read_to_string(&dot_git) {
+            let git_dir = contents.strip_prefix("gitdir: ")?.trim_end();
+
+            return Some((Some(ancestor.to_path_buf()), ancestor.join(git_dir)));
+        }
+    }
+
+    None
+}
+
+// a line from an attributes file that gives some files the lfs filter, found without matching
+// patterns the way git does. it can match files that git wouldn't, but never the other way around
+struct LfsPattern {
+    // where the attributes file is, relative to the working directory
+    dir: Vec<u8>,
+    // set for patterns like *.psd, which is what `git lfs track` writes
+    suffix: Option<Vec<u8>>,
+}
+
+impl LfsPattern {
+    fn may_match(&self, path: &[u8]) -> bool {
+        let has_suffix = match &self.suffix {
+            Some(suffix) => path.ends_with(suffix),
+            None => true,
+        };
+
+        path.starts_with(&self.dir) && has_suffix
+    }
+}
+
+fn parse_lfs_patterns(contents: &[u8], dir: &[u8], patterns: &mut Vec<LfsPattern>) {
+    const SPECIAL: &[u8] = b"*?[\\/\"";
+
+    for line in contents.split(|&b| b == b'\n') {
+        let mut tokens = line
+            .split(|b| b.is_ascii_whitespace())
+            .filter(|t| !t.is_empty());
+
+        let pattern = match tokens.next() {
+            Some(p) if !p.starts_with(b"#") => p,
+            _ => continue,
+        };
+
+        if !tokens.any(|t| t == b"filter=lfs") {
+            continue;
+        }
+
+        // a macro can give the filter to files anywhere
+        if pattern.starts_with(b"[attr]") {
+            patterns.push(LfsPattern {
+                dir: Vec::new(),
+                suffix: None,
+            });
+
+            continue;
+        }
+
+        let suffix = match pattern.strip_prefix(b"*") {
+            Some(s) if !s.iter().any(|b| SPECIAL.contains(b)) => Some(s.to_vec()),
+            _ => None,
+        };
+
+        patterns.push(LfsPattern {
+            dir: dir.to_vec(),
+            suffix,
+        });
+    }
+}
+
+// None if path can't be read. a pointer's first line names the spec it follows
+fn is_lfs_pointer(path: &Path) -> Option<bool> {
+    const HEADER: &[u8] = b"version https://git-lfs";
+
+    let mut header = Vec::with_capacity(HEADER.len());
+    fs::File::open(path)
+        .ok()?
+        .take(HEADER.len() as u64)
+        .read_to_end(&mut header)
+        .ok()?;
+
+    Some(header == HEADER)
+}
+
+// older versions of git only recorded the remote in extensions.partialClone
+fn has_promisor(config: &Config) -> bool {
+    config
+        .get_matching(r"^remote\..*\.promisor$")
+        .iter()
+        .any(|v| parse_bool(v) == Some(true))
+        || !config.get_multivar("extensions.partialClone").is_empty()
+}
+
+// owned by the user, or listed in safe.directory
+fn is_trusted(workdir: Option<&Path>, git_dir: &Path) -> bool {
+    is_owned_by_user(workdir, git_dir) || is_safe_directory(workdir.unwrap_or(git_dir))
+}
+
+fn is_owned_by_user(workdir: Option<&Path>, git_dir: &Path) -> bool {
+    let uid = unsafe { libc::geteuid() };
+
+    // root working in someone's checkout through sudo is really that someone
+    let sudo_uid: Option<u32> = if uid == 0 {
+        env::var("SUDO_UID").ok().and_then(|u| u.parse().ok())
+    } else {
+        None
+    };
+
+    let is_owned = |path: &Path| {
+        fs::metadata(path)
+            .map(|m| m.uid() == uid || Some(m.uid()) == sudo_uid)
+            .unwrap_or(false)
+    };
+
+    workdir.map(is_owned).unwrap_or(true) && is_owned(git_dir)
+}
+
+// only the global and system configuration can vouch for a repository, never its own
+fn is_safe_directory(dir: &Path) -> bool {
+    match Config::open_default() {
+        Some(config) => is_listed_safe(dir, &config.get_multivar("safe.directory")),
+        None => false,
+    }
+}
+
+// libgit2 counts how many times it has been initialized, and every Repository and Config holds
+// one count until it's dropped. an open that fails gives its count back straight away
+fn with_libgit2<T, F: FnOnce() -> Option<T>>(open: F) -> Option<T> {
+    unsafe { libgit2_sys::git_libgit2_init() };
+
+    let opened = open();
+
+    if opened.is_none() {
+        unsafe { libgit2_sys::git_libgit2_shutdown() };
+    }
+
+    opened
+}
+
+// safe_dirs in the order git reads them, where an empty value clears everything before it
+fn is_listed_safe(dir: &Path, safe_dirs: &[String]) -> bool {
+    let dir = trim_trailing_slash(dir);
+    let start = safe_dirs
+        .iter()
+        .rposition(|safe| safe.is_empty())
+        .map(|i| i + 1)
+        .unwrap_or(0);
+
+    safe_dirs[start..].iter().any(|safe| match safe.as_str() {
+        "*" => true,
+        _ => {
+            let safe = expand_tilde(safe);
+
+            match safe.to_str().and_then(|s| s.strip_suffix("/*")) {
+                Some(prefix) => dir.starts_with(prefix),
+                None => trim_trailing_slash(&safe) == dir,
+            }
+        }
+    })
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OpenError {
+    NotFound,
+    // owned by another user and not listed in safe.directory
+    DubiousOwnership,
+}
+
+// true, yes, on, 1 and the like, the way git reads them
+fn parse_bool(value: &str) -> Option<bool> {
+    let value = CString::new(value).ok()?;
+    let mut parsed = 0;
+
+    match unsafe { libgit2_sys::git_config_parse_bool(&mut parsed, value.as_ptr()) } {
+        0 => Some(parsed != 0),
+        _ => None,
+    }
+}
+
+fn trim_trailing_slash(path: &Path) -> &Path {
+    // components() drops the trailing slash that libgit2 leaves on directories
+    path.components().as_path()
+}
+
+fn expand_tilde(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), dirs::home_dir()) {
+        (Some(rest), Some(home_dir)) => home_dir.join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+pub struct Config(*mut git_config);
+
+impl Config {
+    // the global, XDG and system configuration files, without any repository's
+    pub fn open_default() -> Option<Config> {
+        with_libgit2(|| {
+            let mut config = MaybeUninit::uninit();
+
+            match unsafe { libgit2_sys::git_config_open_default(&mut *config.as_mut_ptr()) } {
+                0 => Some(Config(unsafe { config.assume_init() })),
+                _ => None,
+            }
+        })
+    }
+
+    // a single file, like config.worktree
+    pub fn open(path: &Path) -> Option<Config> {
+        let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+
+        with_libgit2(|| {
+            let mut config = MaybeUninit::uninit();
+
+            match unsafe {
+                libgit2_sys::git_config_open_ondisk(&mut *config.as_mut_ptr(), path.as_ptr())
+            } {
+                0 => Some(Config(unsafe { config.assume_init() })),
+                _ => None,
+            }
+        })
+    }
+
+    // every value of a variable that can be set more than once
+    pub fn get_multivar(&self, name: &str) -> Vec<String> {
+        let mut values: Vec<String> = Vec::new();
+
+        let name = match CString::new(name) {
+            Ok(n) => n,
+            Err(_) => return values,
+        };
+        let payload_ptr = &mut values as *mut Vec<String> as *mut c_void;
+
+        unsafe {
+            git_config_get_multivar_foreach(
+                self.0,
+                name.as_ptr(),
+                ptr::null(),
+                Config::multivar_cb_entry,
+                payload_ptr,
+            )
+        };
+
+        values
+    }
+
+    pub fn get_bool(&self, name: &str) -> Option<bool> {
+        let name = CString::new(name).ok()?;
+        let mut value = 0;
+
+        match unsafe { libgit2_sys::git_config_get_bool(&mut value, self.0, name.as_ptr()) } {
+            0 => Some(value != 0),
+            _ => None,
+        }
+    }
+
+    // the values of every variable whose name matches regexp
+    pub fn get_matching(&self, regexp: &str) -> Vec<String> {
+        let mut values: Vec<String> = Vec::new();
+
+        let regexp = match CString::new(regexp) {
+            Ok(r) => r,
+            Err(_) => return values,
+        };
+        let payload_ptr = &mut values as *mut Vec<String> as *mut c_void;
+
+        unsafe {
+            git_config_foreach_match(
+                self.0,
+                regexp.as_ptr(),
+                Config::multivar_cb_entry,
+                payload_ptr,
+            )
+        };
+
+        values
+    }
+
+    extern "C" fn multivar_cb_entry(entry: *const git_config_entry, payload: *mut c_void) -> c_int {
+        let values = unsafe { &mut *(payload as *mut Vec<String>) };
+        let value = unsafe { (*entry).value };
+
+        // a variable with no value at all, not even an empty one
+        if !value.is_null() {
+            let value = unsafe { CStr::from_ptr(value) };
+            values.push(value.to_string_lossy().into_owned());
+        }
+
+        0
+    }
+}
+
+impl Drop for Config {
+    fn drop(&mut self) {
+        unsafe { libgit2_sys::git_config_free(self.0) };
+        unsafe { libgit2_sys::git_libgit2_shutdown() };
+    }
+}
+
+// whether files tracked by git-lfs hold their contents or just pointers to them
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Lfs {
+    Smudged,
+    // some or all of the files may still be pointers
+    Unsmudged,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum State {
+    Clean,
+    Merge,
+    Revert,
+    CherryPick,
+    Bisect,
+    Rebase,
+    RebaseInteractive,
+    RebaseMerge,
+    ApplyMailbox,
+    ApplyMailboxOrRebase,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RebaseProgress {
+    pub step: usize,
+    pub total: usize,
+    pub branch_name: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StatusSummary {
+    pub staged: usize,
+    pub modified: usize,
+    pub deleted: usize,
+    pub renamed: usize,
+    pub untracked: usize,
+    pub conflicted: usize,
+}
+
+impl StatusSummary {
+    fn add(&mut self, flags: c_uint) {
+        const STAGED: c_uint = GIT_STATUS_INDEX_NEW
+            | GIT_STATUS_INDEX_MODIFIED
+            | GIT_STATUS_INDEX_DELETED
+            | GIT_STATUS_INDEX_TYPECHANGE;
+        const MODIFIED: c_uint = GIT_STATUS_WT_MODIFIED | GIT_STATUS_WT_TYPECHANGE;
+        const RENAMED: c_uint = GIT_STATUS_INDEX_RENAMED | GIT_STATUS_WT_RENAMED;
+
+        // conflicted entries carry meaningless index and workdir flags
+        if flags & GIT_STATUS_CONFLICTED != 0 {
+            self.conflicted += 1;
+
+            return;
+        }
+
+        if flags & STAGED != 0 {
+            self.staged += 1;
+        }
+
+        if flags & MODIFIED != 0 {
+            self.modified += 1;
+        }
+
+        if flags & GIT_STATUS_WT_DELETED != 0 {
+            self.deleted += 1;
+        }
+
+        if flags & RENAMED != 0 {
+            self.renamed += 1;
+        }
+
+        if flags & GIT_STATUS_WT_NEW != 0 {
+            self.untracked += 1;
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Untracked {
+    No,
+    Normal,
+    All,
+}
+
+impl Untracked {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Untracked::No => "no",
+            Untracked::Normal => "normal",
+            Untracked::All => "all",
+        }
+    }
+}
+
+impl FromStr for Untracked {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Untracked, ()> {
+        match s {
+            "no" => Ok(Untracked::No),
+            "normal" => Ok(Untracked::Normal),
+            "all" => Ok(Untracked::All),
+            _ => Err(()),
+        }
+    }
+}
+
+pub struct StatusOptions(git_status_options);
+
+impl StatusOptions {
+    pub fn new() -> StatusOptions {
+        let mut options = MaybeUninit::uninit();
+
+        let ret = unsafe {
+            libgit2_sys::git_status_init_options(
+                &mut *options.as_mut_ptr(),
+                GIT_STATUS_OPTIONS_VERSION,
+            )
+        };
+        assert_eq!(ret, 0);
+
+        let mut options = unsafe { options.assume_init() };
+        options.show = GIT_STATUS_SHOW_INDEX_AND_WORKDIR;
+        options.flags = GIT_STATUS_OPT_INCLUDE_UNTRACKED;
+
+        StatusOptions(options)
+    }
+
+    pub fn untracked(&mut self, untracked: Untracked) -> &mut StatusOptions {
+        const UNTRACKED_FLAGS: c_uint =
+            GIT_STATUS_OPT_INCLUDE_UNTRACKED | GIT_STATUS_OPT_RECURSE_UNTRACKED_DIRS;
+
+        self.0.flags &= !UNTRACKED_FLAGS;
+
+        match untracked {
+            Untracked::No => (),
+            Untracked::Normal => self.0.flags |= GIT_STATUS_OPT_INCLUDE_UNTRACKED,
+            Untracked::All => self.0.flags |= UNTRACKED_FLAGS,
+        }
+
+        self
+    }
+
+    pub fn renames(&mut self, renames: bool) -> &mut StatusOptions {
+        const RENAMES_FLAGS: c_uint =
+            GIT_STATUS_OPT_RENAMES_HEAD_TO_INDEX | GIT_STATUS_OPT_RENAMES_INDEX_TO_WORKDIR;
+
+        if renames {
+            self.0.flags |= RENAMES_FLAGS;
+        } else {
+            self.0.flags &= !RENAMES_FLAGS;
+        }
+
+        self
+    }
+
+    pub fn refresh(&mut self, refresh: bool) -> &mut StatusOptions {
+        if refresh {
+            self.0.flags &= !GIT_STATUS_OPT_NO_REFRESH;
+        } else {
+            self.0.flags |= GIT_STATUS_OPT_NO_REFRESH;
+        }
+
+        self
+    }
+}
+
+// git_tag_foreach stops at the first nonzero return from its callback and passes it along
+const TAG_CB_STOP: c_int = 1;
+
+#[repr(C)]
+struct Payload<'a> {
+    repo: &'a Repository,
+    commit: &'a Commit<'a>,
+    limit: Option<usize>,
+    result: Vec<String>,
+}
+
+pub struct Reference<'repo>(*mut git_reference, PhantomData<&'repo Repository>);
+
+impl<'repo> Reference<'repo> {
+    pub fn branch_name(&self) -> Option<&CStr> {
+        let mut name = MaybeUninit::uninit();
+
+        match unsafe { libgit2_sys::git_branch_name(&mut *name.as_mut_ptr(), self.0) } {
+            0 => Some(unsafe { CStr::from_ptr(name.assume_init()) }),
+            _ => None,
+        }
+    }
+
+    pub fn upstream(&self) -> Option<Reference<'repo>> {
+        let mut upstream = MaybeUninit::uninit();
+
+        match unsafe { libgit2_sys::git_branch_upstream(&mut *upstream.as_mut_ptr(), self.0) } {
+            0 => Some(Reference(unsafe { upstream.assume_init() }, PhantomData)),
+            _ => None,
+        }
+    }
+
+    // e.g. "main" for refs/heads/main or "origin/main" for refs/remotes/origin/main
+    pub fn shorthand(&self) -> &CStr {
+        unsafe { CStr::from_ptr(libgit2_sys::git_reference_shorthand(self.0)) }
+    }
+
+    // the name of the remote this branch's upstream belongs to
+    pub fn upstream_remote(&self) -> Option<String> {
+        let mut buf = git_buf {
+            ptr: ptr::null_mut(),
+            asize: 0,
+            size: 0,
+        };
+
+        match unsafe {
+            libgit2_sys::git_branch_upstream_remote(
+                &mut buf,
+                git_reference_owner(self.0),
+                libgit2_sys::git_reference_name(self.0),
+            )
+        } {
+            0 => (),
+            _ => return None,
+        };
+
+        let buf = Buf(buf);
+
+        Some(String::from_utf8_lossy(unsafe { buf.as_slice() }).into_owned())
+    }
+
+    // None unless this is a symbolic reference
+    pub fn symbolic_target(&self) -> Option<&CStr> {
+        let target = unsafe { libgit2_sys::git_reference_symbolic_target(self.0) };
 
-impl<'repo> Reference<'repo> {
-    pub fn branch_name(&self) -> Option<&CStr> {
-        let mut name = MaybeUninit::uninit();
+        if target.is_null() {
+            None
+        } else {
+            Some(unsafe { CStr::from_ptr(target) })
+        }
+    }
 
-        match unsafe { libgit2_sys::git_branch_name(&mut *name.as_mut_ptr(), self.0) } {
-            0 => Some(unsafe { CStr::from_ptr(name.assume_init()) }),
-            _ => None,
+    pub fn target(&self) -> Option<Oid<'_>> {
+        let oid = unsafe { libgit2_sys::git_reference_target(self.0) };
+
+        if oid.is_null() {
+            None // symbolic reference
+        } else {
+            Some(Oid(oid, PhantomData))
         }
     }
 
@@ -158,6 +1515,27 @@ impl<'repo> Drop for Reference<'repo> {
     }
 }
 
+pub struct Submodule<'repo>(*mut git_submodule, PhantomData<&'repo Repository>);
+
+impl<'repo> Submodule<'repo> {
+    // the commit recorded in the superproject's index
+    pub fn index_id(&self) -> Option<Oid<'_>> {
+        let oid = unsafe { libgit2_sys::git_submodule_index_id(self.0) };
+
+        if oid.is_null() {
+            None
+        } else {
+            Some(Oid(oid, PhantomData))
+        }
+    }
+}
+
+impl<'repo> Drop for Submodule<'repo> {
+    fn drop(&mut self) {
+        unsafe { libgit2_sys::git_submodule_free(self.0) };
+    }
+}
+
 pub struct Commit<'repo>(*mut git_commit, PhantomData<&'repo Repository>);
 
 impl<'repo> Commit<'repo> {
@@ -168,6 +1546,62 @@ impl<'repo> Commit<'repo> {
     pub fn id(&self) -> Oid<'repo> {
         Oid(unsafe { libgit2_sys::git_commit_id(self.0) }, PhantomData)
     }
+
+    // committer time in seconds since the Unix epoch
+    pub fn time(&self) -> i64 {
+        unsafe { libgit2_sys::git_commit_time(self.0) }
+    }
+
+    // like `git describe --tags --always --abbrev=7`
+    pub fn describe(&self) -> Option<String> {
+        let mut options = git_describe_options {
+            version: 1,
+            max_candidates_tags: 10,
+            describe_strategy: GIT_DESCRIBE_TAGS,
+            pattern: ptr::null(),
+            only_follow_first_parent: 0,
+            show_commit_oid_as_fallback: 1,
+        };
+        let mut result = MaybeUninit::uninit();
+
+        match unsafe {
+            libgit2_sys::git_describe_commit(
+                &mut *result.as_mut_ptr(),
+                self.0 as *mut git_object,
+                &mut options,
+            )
+        } {
+            0 => (),
+            _ => return None,
+        }
+
+        let result = unsafe { result.assume_init() };
+
+        let format_options = git_describe_format_options {
+            version: 1,
+            abbreviated_size: 7,
+            always_use_long_format: 0,
+            dirty_suffix: ptr::null(),
+        };
+        let mut buf = git_buf {
+            ptr: ptr::null_mut(),
+            asize: 0,
+            size: 0,
+        };
+
+        let ret = unsafe { libgit2_sys::git_describe_format(&mut buf, result, &format_options) };
+        unsafe { libgit2_sys::git_describe_result_free(result) };
+
+        match ret {
+            0 => (),
+            _ => return None,
+        }
+
+        let buf = Buf(buf);
+        let owned = String::from_utf8_lossy(unsafe { buf.as_slice() }).into_owned();
+
+        Some(owned)
+    }
 }
 
 impl<'repo> Drop for Commit<'repo> {
@@ -212,11 +1646,27 @@ impl<'repo> Object<'repo> {
     }
 }
 
+struct Index(*mut git_index);
+
+impl Index {
+    fn entries(&self) -> impl Iterator<Item = &git_index_entry> {
+        let count = unsafe { libgit2_sys::git_index_entrycount(self.0) };
+
+        (0..count).map(move |i| unsafe { &*libgit2_sys::git_index_get_byindex(self.0, i) })
+    }
+}
+
+impl Drop for Index {
+    fn drop(&mut self) {
+        unsafe { libgit2_sys::git_index_free(self.0) };
+    }
+}
+
 struct Buf(git_buf);
 
 impl Buf {
     unsafe fn as_slice(&self) -> &[u8] {
-        slice::from_raw_parts(self.0.ptr as *mut u8, self.0.size as usize)
+        slice::from_raw_parts(self.0.ptr as *mut u8, self.0.size)
     }
 }
 
@@ -229,6 +1679,21 @@ impl Drop for Buf {
 #[derive(Copy, Clone, Debug)]
 pub struct Oid<'repo>(*const git_oid, PhantomData<&'repo Repository>);
 
+impl<'repo> Oid<'repo> {
+    fn to_hex(self) -> [u8; 40] {
+        const DIGITS: &[u8] = b"0123456789abcdef";
+
+        let mut hex = [0; 40];
+
+        for (i, byte) in unsafe { (*self.0).id }.iter().enumerate() {
+            hex[2 * i] = DIGITS[(byte >> 4) as usize];
+            hex[2 * i + 1] = DIGITS[(byte & 0xf) as usize];
+        }
+
+        hex
+    }
+}
+
 impl<'repo> PartialEq for Oid<'repo> {
     fn eq(&self, other: &Oid) -> bool {
         unsafe { libgit2_sys::git_oid_equal(self.0, other.0) != 0 }
@@ -236,3 +1701,808 @@ impl<'repo> PartialEq for Oid<'repo> {
 }
 
 impl<'repo> Eq for Oid<'repo> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{
+        env,
+        ffi::CString,
+        path::PathBuf,
+        process::{self, Command},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use libgit2_sys::{git_signature, git_tree};
+
+    extern "C" {
+        fn git_worktree_add(
+            out: *mut *mut git_worktree,
+            repo: *mut git_repository,
+            name: *const c_char,
+            path: *const c_char,
+            opts: *const c_void,
+        ) -> c_int;
+    }
+
+    #[test]
+    fn tags_pointing_to() {
+        let fixture = Fixture::new("tags_pointing_to", 3);
+
+        fixture.tag("a", 2, false);
+        fixture.tag("b", 2, true);
+        fixture.tag("c", 1, false);
+        fixture.tag("d", 0, true);
+        fixture.tag("nested/f", 2, true);
+        fixture.pack_refs();
+
+        // loose tags shadow packed ones
+        fixture.tag("a", 0, false);
+        fixture.tag("c", 2, false);
+        fixture.tag("e", 2, true);
+
+        let commit = fixture.commit(2);
+        let expected = ["b", "c", "e", "nested/f"];
+
+        let mut packed = fixture.repo.tags_pointing_to_packed(&commit, None).unwrap();
+        packed.sort();
+        assert_eq!(packed, expected);
+
+        let mut foreach = fixture
+            .repo
+            .tags_pointing_to_foreach(&commit, None)
+            .unwrap();
+        foreach.sort();
+        assert_eq!(foreach, expected);
+
+        assert_eq!(
+            fixture.repo.tags_pointing_to(&commit, None).unwrap(),
+            expected
+        );
+        assert_eq!(
+            fixture.repo.tags_pointing_to(&commit, Some(2)).unwrap(),
+            ["b", "c"]
+        );
+
+        // the packed scan stops after the first packed match, but the loose ones are all kept
+        let mut packed = fixture
+            .repo
+            .tags_pointing_to_packed(&commit, Some(1))
+            .unwrap();
+        packed.sort();
+        assert_eq!(packed, ["b", "c", "e"]);
+        assert_eq!(
+            fixture
+                .repo
+                .tags_pointing_to_foreach(&commit, Some(1))
+                .unwrap()
+                .len(),
+            1
+        );
+        assert!(fixture
+            .repo
+            .tags_pointing_to(&fixture.commit(1), None)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn tags_pointing_to_broken_loose_tag() {
+        let fixture = Fixture::new("tags_pointing_to_broken_loose_tag", 1);
+
+        fixture.tag("a", 0, true);
+        fixture.tag("b", 0, false);
+        fixture.pack_refs();
+
+        // a loose tag that doesn't resolve doesn't hide the packed one
+        fs::write(fixture.path.join(".git/refs/tags/a"), "garbage\n").unwrap();
+
+        let commit = fixture.commit(0);
+
+        assert_eq!(
+            fixture.repo.tags_pointing_to(&commit, None).unwrap(),
+            ["a", "b"]
+        );
+    }
+
+    #[test]
+    fn tags_pointing_to_without_packed_refs() {
+        let fixture = Fixture::new("tags_pointing_to_without_packed_refs", 1);
+
+        fixture.tag("a", 0, true);
+        fixture.tag("b", 0, false);
+
+        let commit = fixture.commit(0);
+
+        assert!(fixture
+            .repo
+            .tags_pointing_to_packed(&commit, None)
+            .is_none());
+        assert_eq!(
+            fixture.repo.tags_pointing_to(&commit, None).unwrap(),
+            ["a", "b"]
+        );
+        assert_eq!(
+            fixture
+                .repo
+                .tags_pointing_to(&commit, Some(1))
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn ahead_behind() {
+        let fixture = Fixture::new("ahead_behind", 3);
+        fixture.set_upstream("origin", "main", 1);
+
+        let head = fixture.repo.head().unwrap();
+        let upstream = head.upstream().unwrap();
+        assert!(upstream.target().unwrap() == fixture.commit(1).id());
+
+        let local = head.target().unwrap();
+        assert_eq!(
+            fixture.repo.ahead_behind(local, upstream.target().unwrap()),
+            Some((1, 0))
+        );
+        assert_eq!(
+            fixture.repo.ahead_behind(fixture.commit(0).id(), local),
+            Some((0, 2))
+        );
+    }
+
+    #[test]
+    fn rebase_progress() {
+        let fixture = Fixture::new("rebase_progress", 1);
+
+        assert_eq!(fixture.repo.state(), State::Clean);
+        assert_eq!(fixture.repo.rebase_progress(), None);
+
+        let merge_dir = fixture.path.join(".git/rebase-merge");
+        fs::create_dir(&merge_dir).unwrap();
+        fs::write(merge_dir.join("interactive"), "").unwrap();
+        fs::write(merge_dir.join("msgnum"), "3\n").unwrap();
+        fs::write(merge_dir.join("end"), "7\n").unwrap();
+        fs::write(merge_dir.join("head-name"), "refs/heads/feature\n").unwrap();
+
+        assert_eq!(fixture.repo.state(), State::RebaseInteractive);
+        assert_eq!(
+            fixture.repo.rebase_progress(),
+            Some(RebaseProgress {
+                step: 3,
+                total: 7,
+                branch_name: Some("feature".to_string()),
+            })
+        );
+
+        fs::remove_dir_all(&merge_dir).unwrap();
+
+        let apply_dir = fixture.path.join(".git/rebase-apply");
+        fs::create_dir(&apply_dir).unwrap();
+        fs::write(apply_dir.join("rebasing"), "").unwrap();
+        fs::write(apply_dir.join("next"), "1\n").unwrap();
+        fs::write(apply_dir.join("last"), "2\n").unwrap();
+        fs::write(apply_dir.join("head-name"), "detached HEAD\n").unwrap();
+
+        assert_eq!(fixture.repo.state(), State::Rebase);
+        assert_eq!(
+            fixture.repo.rebase_progress(),
+            Some(RebaseProgress {
+                step: 1,
+                total: 2,
+                branch_name: None,
+            })
+        );
+    }
+
+    #[test]
+    fn describe() {
+        let fixture = Fixture::new("describe", 3);
+        let short_id = |index: usize| {
+            String::from_utf8(fixture.commit(index).id().to_hex()[..7].to_vec()).unwrap()
+        };
+
+        // falls back to the short id without any tags
+        assert_eq!(fixture.commit(2).describe().unwrap(), short_id(2));
+
+        fixture.tag("v1", 0, true);
+        fixture.tag("v2", 1, false);
+
+        assert_eq!(fixture.commit(0).describe().unwrap(), "v1");
+        assert_eq!(fixture.commit(1).describe().unwrap(), "v2");
+        assert_eq!(
+            fixture.commit(2).describe().unwrap(),
+            format!("v2-1-g{}", short_id(2))
+        );
+    }
+
+    #[test]
+    fn worktree_name() {
+        let fixture = Fixture::new("worktree_name", 1);
+        let path = env::temp_dir().join(format!("clanker-worktree_name-wt-{}", process::id()));
+        let _ = fs::remove_dir_all(&path);
+
+        let c_path = CString::new(path.as_os_str().as_bytes()).unwrap();
+        let mut worktree = ptr::null_mut();
+        assert_eq!(
+            unsafe {
+                git_worktree_add(
+                    &mut worktree,
+                    fixture.repo.0,
+                    b"linked\0".as_ptr() as *const c_char,
+                    c_path.as_ptr(),
+                    ptr::null(),
+                )
+            },
+            0
+        );
+        unsafe { git_worktree_free(worktree) };
+
+        assert!(!fixture.repo.is_worktree());
+        assert_eq!(fixture.repo.worktree_name(), None);
+
+        let linked = Repository::open(&path).unwrap();
+        assert!(linked.is_worktree());
+        assert_eq!(linked.worktree_name().as_deref(), Some("linked"));
+        assert_eq!(
+            trim_trailing_slash(linked.commondir()),
+            trim_trailing_slash(fixture.repo.path())
+        );
+
+        drop(linked);
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn upstream_remote() {
+        let fixture = Fixture::new("upstream_remote", 1);
+        let head = fixture.repo.head().unwrap();
+
+        assert!(head.upstream().is_none());
+        assert_eq!(head.upstream_remote(), None);
+
+        // remote names can contain slashes too
+        fixture.set_upstream("up/stream", "release-2.x", 0);
+
+        let upstream = head.upstream().unwrap();
+        assert_eq!(upstream.shorthand().to_str(), Ok("up/stream/release-2.x"));
+        assert_eq!(head.upstream_remote().as_deref(), Some("up/stream"));
+    }
+
+    #[test]
+    fn commit_time() {
+        let fixture = Fixture::new("commit_time", 1);
+
+        assert_eq!(fixture.commit(0).time(), COMMIT_TIME);
+    }
+
+    #[test]
+    fn unborn_branch_name() {
+        let fixture = Fixture::new("unborn_branch_name", 0);
+
+        assert_eq!(
+            unsafe {
+                libgit2_sys::git_repository_set_head(
+                    fixture.repo.0,
+                    b"refs/heads/trunk\0".as_ptr() as *const c_char,
+                )
+            },
+            0
+        );
+
+        assert!(fixture.repo.head().is_none());
+        assert!(fixture.repo.is_head_unborn());
+        assert_eq!(fixture.repo.unborn_branch_name().as_deref(), Some("trunk"));
+
+        let fixture = Fixture::new("unborn_branch_name-born", 1);
+
+        assert!(!fixture.repo.is_head_unborn());
+        assert_eq!(fixture.repo.unborn_branch_name(), None);
+    }
+
+    #[test]
+    fn vcs() {
+        let fixture = Fixture::new("vcs", 2);
+        let vcs = crate::vcs::discover(&fixture.path).unwrap();
+        let branch_name = fixture
+            .repo
+            .head()
+            .unwrap()
+            .shorthand()
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        assert!(vcs.is_git());
+        assert_eq!(vcs.identify(), Some(branch_name));
+        assert_eq!(vcs.operation(), None);
+        assert_eq!(vcs.is_modified(), Some(false));
+
+        fs::write(fixture.path.join("a"), "").unwrap();
+        assert_eq!(vcs.is_modified(), Some(true));
+
+        fixture.tag("v1", 0, true);
+        let hex = fixture.commit(1).id().to_hex();
+        fs::write(fixture.path.join(".git/HEAD"), [&hex[..], b"\n"].concat()).unwrap();
+        assert_eq!(
+            vcs.identify().unwrap(),
+            format!("v1-1-g{}", str::from_utf8(&hex[..7]).unwrap())
+        );
+
+        let merge_dir = fixture.path.join(".git/rebase-merge");
+        fs::create_dir(&merge_dir).unwrap();
+        fs::write(merge_dir.join("interactive"), "").unwrap();
+        fs::write(merge_dir.join("msgnum"), "3\n").unwrap();
+        fs::write(merge_dir.join("end"), "7\n").unwrap();
+        fs::write(merge_dir.join("head-name"), "refs/heads/feature\n").unwrap();
+
+        assert_eq!(vcs.identify().as_deref(), Some("feature"));
+        assert_eq!(vcs.operation().as_deref(), Some("REBASE-i 3/7"));
+    }
+
+    #[test]
+    fn lfs() {
+        const POINTER: &str = "version https://git-lfs.github.com/spec/v1\n\
+                               oid sha256:4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393\n\
+                               size 12345\n";
+
+        let fixture = Fixture::new("lfs", 1);
+        let config = fixture.repo.config().unwrap();
+
+        fixture.add("a.bin", POINTER);
+        assert_eq!(fixture.repo.lfs(&config), None);
+
+        // attributes files anywhere in the tree count, not just the top level
+        fixture.add(
+            "assets/.gitattributes",
+            "*.bin filter=lfs diff=lfs merge=lfs -text\n",
+        );
+        fixture.add("assets/b.bin", POINTER);
+        assert_eq!(fixture.repo.lfs(&config), Some(Lfs::Unsmudged));
+
+        fs::write(fixture.path.join("assets/b.bin"), "contents").unwrap();
+        assert_eq!(fixture.repo.lfs(&config), Some(Lfs::Smudged));
+
+        // a.bin is outside of assets, so it never had the filter
+        fs::write(fixture.path.join("a.bin"), "contents").unwrap();
+        assert_eq!(fixture.repo.lfs(&config), Some(Lfs::Smudged));
+    }
+
+    #[test]
+    fn parse_lfs_patterns() {
+        let mut patterns = Vec::new();
+        super::parse_lfs_patterns(
+            b"# *.txt filter=lfs\n\
+              *.psd filter=lfs diff=lfs merge=lfs -text\n\
+              *.txt text\n\
+              \n\
+              models/** filter=lfs\n",
+            b"assets/",
+            &mut patterns,
+        );
+
+        assert_eq!(patterns.len(), 2);
+        assert!(patterns[0].may_match(b"assets/art/logo.psd"));
+        assert!(!patterns[0].may_match(b"assets/readme.txt"));
+        assert!(!patterns[0].may_match(b"logo.psd"));
+        assert!(patterns[1].may_match(b"assets/models/a.obj"));
+
+        // macros can be used anywhere, so everything might match
+        super::parse_lfs_patterns(b"[attr]big filter=lfs -text\n", b"assets/", &mut patterns);
+        assert!(patterns[2].may_match(b"readme.txt"));
+    }
+
+    #[test]
+    fn is_listed_safe() {
+        let safe = |dirs: &[&str]| {
+            let dirs: Vec<String> = dirs.iter().map(|d| d.to_string()).collect();
+
+            super::is_listed_safe(Path::new("/srv/repo/"), &dirs)
+        };
+
+        assert!(safe(&["/srv/repo"]));
+        assert!(safe(&["/srv/*"]));
+        assert!(safe(&["*"]));
+        assert!(!safe(&["/srv/other"]));
+        assert!(!safe(&[]));
+        // an empty value resets the list
+        assert!(!safe(&["*", ""]));
+        assert!(safe(&["/srv/other", "", "/srv/repo"]));
+    }
+
+    #[test]
+    fn unsupported_repository() {
+        let path = env::temp_dir().join(format!("clanker-unsupported-{}", process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(path.join(".git")).unwrap();
+        fs::create_dir_all(path.join("sub")).unwrap();
+
+        fs::write(path.join(".git/HEAD"), "ref: refs/heads/main\n").unwrap();
+        fs::write(
+            path.join(".git/config"),
+            "[core]\n\trepositoryformatversion = 1\n\
+             [remote \"origin\"]\n\tpromisor = true\n",
+        )
+        .unwrap();
+
+        let repo = UnsupportedRepository::discover(&path.join("sub"), true).unwrap();
+        assert_eq!(repo.branch_name(), Some("main"));
+        assert!(repo.is_partial_clone());
+        assert!(!repo.is_shallow());
+
+        fs::write(path.join(".git/HEAD"), format!("{}\n", "ab".repeat(20))).unwrap();
+        let repo = UnsupportedRepository::discover(&path, true).unwrap();
+        assert_eq!(repo.branch_name(), None);
+        assert_eq!(repo.short_id(), Some("abababa"));
+
+        // libgit2 opens these itself
+        fs::write(
+            path.join(".git/config"),
+            "[core]\n\trepositoryformatversion = 0\n",
+        )
+        .unwrap();
+        assert!(UnsupportedRepository::discover(&path, true).is_err());
+
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn status() {
+        let fixture = Fixture::new("status", 1);
+        let mut options = StatusOptions::new();
+
+        assert_eq!(fixture.repo.is_dirty(&options), Some(false));
+        assert_eq!(
+            fixture.repo.status(&options),
+            Some(StatusSummary::default())
+        );
+
+        fs::create_dir(fixture.path.join("dir")).unwrap();
+        fs::write(fixture.path.join("dir/a"), "").unwrap();
+        fs::write(fixture.path.join("dir/b"), "").unwrap();
+
+        assert_eq!(fixture.repo.is_dirty(&options), Some(true));
+        // an untracked directory counts once unless we look inside it
+        assert_eq!(fixture.repo.status(&options).unwrap().untracked, 1);
+
+        options.untracked(Untracked::All);
+        assert_eq!(fixture.repo.status(&options).unwrap().untracked, 2);
+
+        options.untracked(Untracked::No).refresh(false);
+        assert_eq!(fixture.repo.is_dirty(&options), Some(false));
+    }
+
+    #[test]
+    fn status_summary_add() {
+        let mut summary = StatusSummary::default();
+
+        summary.add(GIT_STATUS_INDEX_NEW | GIT_STATUS_WT_MODIFIED);
+        summary.add(GIT_STATUS_INDEX_RENAMED);
+        summary.add(GIT_STATUS_WT_DELETED);
+        summary.add(GIT_STATUS_WT_NEW);
+        // the other flags don't count once a file is conflicted
+        summary.add(GIT_STATUS_CONFLICTED | GIT_STATUS_INDEX_MODIFIED);
+
+        assert_eq!(
+            summary,
+            StatusSummary {
+                staged: 1,
+                modified: 1,
+                deleted: 1,
+                renamed: 1,
+                untracked: 1,
+                conflicted: 1,
+            }
+        );
+    }
+
+    // cargo test --release -- --ignored --nocapture bench_status. needs git on the PATH, since
+    // the point is to compare against it
+    #[test]
+    #[ignore]
+    fn bench_status() {
+        const NUM_DIRS: usize = 100;
+        const NUM_FILES: usize = 20_000;
+
+        let fixture = Fixture::new("bench_status", 1);
+
+        for i in 0..NUM_FILES {
+            let dir = fixture.path.join(format!("d{}", i % NUM_DIRS));
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join(format!("f{}", i)), i.to_string()).unwrap();
+        }
+
+        let git = |args: &[&str]| {
+            Command::new("git")
+                .args([
+                    "-c",
+                    "user.name=clanker",
+                    "-c",
+                    "user.email=clanker@example.com",
+                ])
+                .args(args)
+                .current_dir(&fixture.path)
+                .output()
+                .unwrap()
+        };
+
+        assert!(git(&["add", "-A"]).status.success());
+        assert!(git(&["commit", "-q", "-m", "files"]).status.success());
+
+        // files written in the same second as the index have to be read to be sure they're
+        // unchanged, which a real checkout rarely needs
+        thread::sleep(Duration::from_secs(2));
+        assert!(git(&["update-index", "--refresh"]).status.success());
+
+        let mut options = StatusOptions::new();
+        options.refresh(false);
+
+        let measure = |label| {
+            let start = Instant::now();
+            let porcelain = git(&["status", "--porcelain"]);
+            let git_elapsed = start.elapsed();
+
+            let start = Instant::now();
+            let repo = Repository::open(&fixture.path).unwrap();
+            let is_dirty = repo.is_dirty(&options);
+            let libgit2_elapsed = start.elapsed();
+
+            println!(
+                "{} files, {}: git status {:?}, libgit2 {:?}",
+                NUM_FILES, label, git_elapsed, libgit2_elapsed
+            );
+
+            assert_eq!(is_dirty, Some(!porcelain.stdout.is_empty()));
+        };
+
+        // a clean tree is the worst case, since nothing cuts the walk short
+        measure("clean");
+
+        fs::write(fixture.path.join("d0/f0"), "changed").unwrap();
+        measure("dirty");
+    }
+
+    // cargo test --release -- --ignored --nocapture bench_tags_pointing_to
+    #[test]
+    #[ignore]
+    fn bench_tags_pointing_to() {
+        const NUM_COMMITS: usize = 100;
+        const NUM_TAGS: usize = 40_000;
+
+        let fixture = Fixture::new("bench_tags_pointing_to", NUM_COMMITS);
+
+        for i in 0..NUM_TAGS {
+            fixture.tag(&format!("v{}", i), i % NUM_COMMITS, i % 2 == 0);
+        }
+
+        fixture.pack_refs();
+
+        let commit = fixture.commit(NUM_COMMITS - 1);
+
+        let start = Instant::now();
+        let mut foreach = fixture
+            .repo
+            .tags_pointing_to_foreach(&commit, None)
+            .unwrap();
+        let foreach_elapsed = start.elapsed();
+
+        let start = Instant::now();
+        let mut packed = fixture.repo.tags_pointing_to_packed(&commit, None).unwrap();
+        let packed_elapsed = start.elapsed();
+
+        println!(
+            "{} tags: git_tag_foreach {:?}, packed-refs {:?}",
+            NUM_TAGS, foreach_elapsed, packed_elapsed
+        );
+
+        foreach.sort();
+        packed.sort();
+        assert_eq!(foreach, packed);
+        assert_eq!(foreach.len(), NUM_TAGS / NUM_COMMITS);
+    }
+
+    const COMMIT_TIME: i64 = 1_600_000_000;
+
+    struct Fixture {
+        repo: Repository,
+        path: PathBuf,
+        signature: *mut git_signature,
+        commits: Vec<git_oid>,
+    }
+
+    impl Fixture {
+        // a repository with a linear history of empty commits
+        fn new(name: &str, num_commits: usize) -> Fixture {
+            let path = env::temp_dir().join(format!("clanker-{}-{}", name, process::id()));
+            let _ = fs::remove_dir_all(&path);
+
+            unsafe { libgit2_sys::git_libgit2_init() };
+
+            let c_path = CString::new(path.as_os_str().as_bytes()).unwrap();
+            let mut raw = ptr::null_mut();
+            assert_eq!(
+                unsafe { libgit2_sys::git_repository_init(&mut raw, c_path.as_ptr(), 0) },
+                0
+            );
+            let repo = Repository(raw);
+
+            let mut signature = ptr::null_mut();
+            assert_eq!(
+                unsafe {
+                    libgit2_sys::git_signature_new(
+                        &mut signature,
+                        b"clanker\0".as_ptr() as *const c_char,
+                        b"clanker@example.com\0".as_ptr() as *const c_char,
+                        COMMIT_TIME,
+                        0,
+                    )
+                },
+                0
+            );
+
+            let mut builder = ptr::null_mut();
+            let mut tree_id = MaybeUninit::uninit();
+            let mut tree: *mut git_tree = ptr::null_mut();
+
+            unsafe {
+                assert_eq!(
+                    libgit2_sys::git_treebuilder_new(&mut builder, raw, ptr::null()),
+                    0
+                );
+                assert_eq!(
+                    libgit2_sys::git_treebuilder_write(&mut *tree_id.as_mut_ptr(), builder),
+                    0
+                );
+                libgit2_sys::git_treebuilder_free(builder);
+                assert_eq!(
+                    libgit2_sys::git_tree_lookup(&mut tree, raw, tree_id.as_ptr()),
+                    0
+                );
+            }
+
+            let mut fixture = Fixture {
+                repo,
+                path,
+                signature,
+                commits: Vec::new(),
+            };
+
+            for _ in 0..num_commits {
+                let parent = fixture.commits.last().map(|oid| fixture.lookup_commit(oid));
+                let mut parents: Vec<_> = parent.iter().map(|&p| p as *const git_commit).collect();
+                let mut oid = MaybeUninit::uninit();
+
+                assert_eq!(
+                    unsafe {
+                        libgit2_sys::git_commit_create(
+                            &mut *oid.as_mut_ptr(),
+                            raw,
+                            b"HEAD\0".as_ptr() as *const c_char,
+                            signature,
+                            signature,
+                            ptr::null(),
+                            b"commit\0".as_ptr() as *const c_char,
+                            tree,
+                            parents.len(),
+                            parents.as_mut_ptr(),
+                        )
+                    },
+                    0
+                );
+
+                if let Some(parent) = parent {
+                    unsafe { libgit2_sys::git_commit_free(parent) };
+                }
+
+                fixture.commits.push(unsafe { oid.assume_init() });
+            }
+
+            unsafe { libgit2_sys::git_tree_free(tree) };
+
+            fixture
+        }
+
+        fn lookup_commit(&self, oid: &git_oid) -> *mut git_commit {
+            let mut commit = ptr::null_mut();
+            assert_eq!(
+                unsafe { libgit2_sys::git_commit_lookup(&mut commit, self.repo.0, oid) },
+                0
+            );
+
+            commit
+        }
+
+        fn commit(&self, index: usize) -> Commit<'_> {
+            Commit(self.lookup_commit(&self.commits[index]), PhantomData)
+        }
+
+        fn tag(&self, name: &str, index: usize, annotated: bool) {
+            let commit = self.commit(index);
+            let target = commit.as_object().0;
+            let name = CString::new(name).unwrap();
+            let mut oid = MaybeUninit::uninit();
+
+            let ret = unsafe {
+                if annotated {
+                    libgit2_sys::git_tag_create(
+                        &mut *oid.as_mut_ptr(),
+                        self.repo.0,
+                        name.as_ptr(),
+                        target,
+                        self.signature,
+                        b"tag\0".as_ptr() as *const c_char,
+                        1,
+                    )
+                } else {
+                    libgit2_sys::git_tag_create_lightweight(
+                        &mut *oid.as_mut_ptr(),
+                        self.repo.0,
+                        name.as_ptr(),
+                        target,
+                        1,
+                    )
+                }
+            };
+            assert_eq!(ret, 0);
+        }
+
+        // makes the current branch track remote/branch, which points at the given commit
+        fn set_upstream(&self, remote: &str, branch: &str, index: usize) {
+            let head = self.repo.head().unwrap();
+            let local = head.branch_name().unwrap().to_str().unwrap();
+
+            let mut config = fs::read_to_string(self.path.join(".git/config")).unwrap();
+            config.push_str(&format!(
+                "[remote \"{0}\"]\n\turl = /dev/null\n\
+                 \tfetch = +refs/heads/*:refs/remotes/{0}/*\n\
+                 [branch \"{1}\"]\n\tremote = {0}\n\tmerge = refs/heads/{2}\n",
+                remote, local, branch
+            ));
+            fs::write(self.path.join(".git/config"), config).unwrap();
+
+            let ref_path = self
+                .path
+                .join(".git/refs/remotes")
+                .join(remote)
+                .join(branch);
+            fs::create_dir_all(ref_path.parent().unwrap()).unwrap();
+            let hex = self.commit(index).id().to_hex();
+            fs::write(ref_path, [&hex[..], b"\n"].concat()).unwrap();
+        }
+
+        // writes a file and stages it
+        fn add(&self, path: &str, contents: &str) {
+            let full_path = self.path.join(path);
+            fs::create_dir_all(full_path.parent().unwrap()).unwrap();
+            fs::write(full_path, contents).unwrap();
+
+            let index = self.repo.index().unwrap();
+            let path = CString::new(path).unwrap();
+
+            unsafe {
+                assert_eq!(libgit2_sys::git_index_add_bypath(index.0, path.as_ptr()), 0);
+                assert_eq!(libgit2_sys::git_index_write(index.0), 0);
+            }
+        }
+
+        fn pack_refs(&self) {
+            let mut refdb = ptr::null_mut();
+
+            unsafe {
+                assert_eq!(libgit2_sys::git_refdb_open(&mut refdb, self.repo.0), 0);
+                assert_eq!(libgit2_sys::git_refdb_compress(refdb), 0);
+                libgit2_sys::git_refdb_free(refdb);
+            }
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            unsafe { libgit2_sys::git_signature_free(self.signature) };
+            let _ = fs::remove_dir_all(&self.path);
+        }
+    }
+}
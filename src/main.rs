@@ -15,15 +15,35 @@
 
 mod color;
 mod compress;
+mod daemon;
 mod git;
+mod truncate;
+mod vcs;
 
 #[macro_use]
 extern crate clap;
 
 use color::Color;
-use git::Repository;
+use compress::{
+    Alias, Anchors, ExpandError, FirstGrapheme, FixedLength, LastComponents, NoCompression,
+    Strategy,
+};
+use daemon::TreeStatus;
+use git::{Lfs, OpenError, Reference, Repository, StatusSummary, UnsupportedRepository, Untracked};
+use truncate::Truncation;
+use vcs::Vcs;
 
-use std::{env, ffi::CStr, mem::MaybeUninit, process::Command, thread};
+use std::{
+    cmp, env,
+    ffi::{CStr, OsStr, OsString},
+    fmt::Write,
+    mem::MaybeUninit,
+    path::{Path, PathBuf},
+    process,
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
 use clap::{AppSettings, Arg, ArgMatches, SubCommand};
 
@@ -64,6 +84,62 @@ fn main() {
             }
         });
 
+    let compression_arg = Arg::with_name("compression")
+        .long("compression")
+        .value_name("STRATEGY")
+        .help(
+            "How directories are shortened: to their shortest \"unique\" prefix, to a \
+             \"fixed\" number of characters, to their \"first\" character, by keeping only the \
+             \"last\" few whole, or \"none\" at all.",
+        )
+        .possible_values(&["unique", "fixed", "first", "last", "none"])
+        .default_value("unique");
+
+    let compression_length_arg = Arg::with_name("compression_length")
+        .long("compression-length")
+        .value_name("COUNT")
+        .help("How many characters \"fixed\" keeps, or how many directories \"last\" keeps")
+        .default_value("3")
+        .validator(|maybe_length| match maybe_length.parse::<usize>() {
+            Ok(l) if l > 0 => Ok(()),
+            _ => Err("expected a positive integer".to_string()),
+        });
+
+    let anchors_arg = Arg::with_name("anchors")
+        .long("anchors")
+        .help("If set, directories holding any of the anchor markers are never compressed");
+
+    let anchor_markers_arg = Arg::with_name("anchor_markers")
+        .long("anchor-markers")
+        .value_name("MARKERS")
+        .help(
+            "Comma-separated names of the files or directories that make a directory an \
+             anchor. Implies --anchors.",
+        )
+        .use_delimiter(true)
+        .default_value(".git,Cargo.toml,package.json");
+
+    let bold_anchors_arg = Arg::with_name("bold_anchors")
+        .long("bold-anchors")
+        .help("If set, anchor directories are printed in bold. Implies --anchors.");
+
+    let alias_arg = Arg::with_name("alias")
+        .long("alias")
+        .value_name("LABEL=PATH")
+        .help(
+            "Shows PATH and the directories under it as LABEL, like ~proj for /srv/projects. \
+             LABEL must start with ~. May be given more than once; the longest matching alias or \
+             home directory wins.",
+        )
+        .multiple(true)
+        .number_of_values(1)
+        .validator(|maybe_alias| match maybe_alias.parse::<Alias>() {
+            Ok(_) => Ok(()),
+            Err(_) => Err(
+                "expected LABEL=PATH with a LABEL starting with ~ and an absolute PATH".to_string(),
+            ),
+        });
+
     let working_directory_arg = Arg::with_name("working_directory")
         .short("w")
         .long("working-directory")
@@ -99,6 +175,43 @@ fn main() {
                         .long("no-username-hostname")
                         .help("If set, the current username and hostname will not be output"),
                 )
+                .arg(
+                    Arg::with_name("max_width")
+                        .short("W")
+                        .long("max-width")
+                        .value_name("WIDTH")
+                        .min_values(0)
+                        .max_values(1)
+                        .help(
+                            "If set, directories are only compressed, from the left, until the \
+                             working directory fits in WIDTH columns, and leading directories \
+                             are replaced with '…' if even that isn't enough. WIDTH defaults to \
+                             $COLUMNS less the rest of the prompt, which doesn't leave room for \
+                             the right prompt.",
+                        )
+                        .validator(|maybe_max_width| {
+                            if maybe_max_width.parse::<usize>().is_err() {
+                                Err("expected an integer".to_string())
+                            } else {
+                                Ok(())
+                            }
+                        }),
+                )
+                .arg(
+                    Arg::with_name("repo_relative")
+                        .short("R")
+                        .long("repo-relative")
+                        .help(
+                            "If set, a path inside a git repository is shown relative to the \
+                             repository, starting with the repository's name in bold",
+                        ),
+                )
+                .arg(compression_arg.clone())
+                .arg(compression_length_arg.clone())
+                .arg(anchors_arg.clone())
+                .arg(anchor_markers_arg.clone())
+                .arg(bold_anchors_arg.clone())
+                .arg(alias_arg.clone())
                 .arg(min_home_dir_uid_arg.clone())
                 .arg(max_home_dir_uid_arg.clone())
                 .arg(working_directory_arg.clone()),
@@ -120,6 +233,124 @@ fn main() {
                                 Ok(())
                             }
                         }),
+                )
+                .arg(
+                    Arg::with_name("untracked_files")
+                        .short("U")
+                        .long("untracked-files")
+                        .value_name("MODE")
+                        .help(
+                            "Whether untracked files make the repository dirty. \"normal\" \
+                             does not look inside untracked directories, \"all\" does.",
+                        )
+                        .possible_values(&["no", "normal", "all"])
+                        .default_value("normal"),
+                )
+                .arg(Arg::with_name("describe").short("D").long("describe").help(
+                    "If set, a detached HEAD is identified like `git describe --tags`, \
+                             by its nearest tag and the number of commits since that tag.",
+                ))
+                .arg(
+                    Arg::with_name("max_tags")
+                        .short("T")
+                        .long("max-tags")
+                        .value_name("COUNT")
+                        .help(
+                            "Maximum number of tags shown for a detached HEAD. The search for \
+                             tags stops once this many are found.",
+                        )
+                        .validator(|maybe_max_tags| match maybe_max_tags.parse::<usize>() {
+                            Ok(m) if m > 0 => Ok(()),
+                            _ => Err("expected a positive integer".to_string()),
+                        }),
+                )
+                .arg(
+                    Arg::with_name("status_timeout")
+                        .short("t")
+                        .long("status-timeout")
+                        .value_name("MILLISECONDS")
+                        .help(
+                            "How long to wait for the working tree status. If it takes longer, \
+                             '…' is printed in place of the status.",
+                        )
+                        .validator(|maybe_status_timeout| {
+                            if maybe_status_timeout.parse::<u64>().is_err() {
+                                Err("expected an integer".to_string())
+                            } else {
+                                Ok(())
+                            }
+                        }),
+                )
+                .arg(
+                    Arg::with_name("detailed_status")
+                        .short("s")
+                        .long("detailed-status")
+                        .help(
+                            "If set, uncommitted changes are counted by kind instead of being \
+                             marked with a single '*'. This is slower in large repositories.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("max_branch_width")
+                        .short("w")
+                        .long("max-branch-width")
+                        .value_name("WIDTH")
+                        .help(
+                            "Maximum width of the branch name. Longer names are shortened with \
+                             '…', keeping ticket IDs like PROJ-12345 intact when possible.",
+                        )
+                        .validator(|maybe_max_branch_width| {
+                            match maybe_max_branch_width.parse::<usize>() {
+                                Ok(m) if m > 0 => Ok(()),
+                                _ => Err("expected a positive integer".to_string()),
+                            }
+                        }),
+                )
+                .arg(
+                    Arg::with_name("truncate")
+                        .long("truncate")
+                        .value_name("STRATEGY")
+                        .help(
+                            "Which part of a long branch name to keep: its \"prefix\", its \
+                             \"suffix\", or both ends with the \"middle\" elided.",
+                        )
+                        .possible_values(&["prefix", "suffix", "middle"])
+                        .default_value("middle"),
+                )
+                .arg(
+                    Arg::with_name("show_upstream")
+                        .short("u")
+                        .long("show-upstream")
+                        .help(
+                            "If set, a branch whose upstream has a different name is shown \
+                             along with its upstream, like \"local→origin/release\".",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("commit_age")
+                        .short("a")
+                        .long("commit-age")
+                        .help(
+                            "If set, the age of the commit HEAD points to is shown, like \"5d\". \
+                             It is green under a day old, yellow under a week, and red after.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("allow_dubious_ownership")
+                        .long("allow-dubious-ownership")
+                        .help(
+                            "If set, repositories owned by other users are shown even if they \
+                             aren't listed in safe.directory.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("submodules")
+                        .short("S")
+                        .long("submodules")
+                        .help(
+                            "If set, the number of submodules with new commits or uncommitted \
+                             changes is shown. Each submodule's status is read separately.",
+                        ),
                 ),
         )
         .subcommand(
@@ -133,57 +364,78 @@ fn main() {
                         .help("Name of the currently running command")
                         .takes_value(true),
                 )
+                .arg(compression_arg.clone())
+                .arg(compression_length_arg.clone())
+                .arg(anchors_arg.clone())
+                .arg(anchor_markers_arg.clone())
+                .arg(alias_arg.clone())
                 .arg(min_home_dir_uid_arg.clone())
                 .arg(max_home_dir_uid_arg.clone())
                 .arg(working_directory_arg.clone()),
         )
+        .subcommand(
+            SubCommand::with_name("expand")
+                .about(
+                    "Expands a compressed path back into the directory it stands for. If a \
+                     component is ambiguous, every directory it could stand for is printed \
+                     instead, one per line, and the exit status is 2",
+                )
+                .arg(
+                    Arg::with_name("path")
+                        .value_name("PATH")
+                        .help("Compressed path, like ~/r/cl/s")
+                        .required(true),
+                )
+                .arg(alias_arg.clone())
+                .arg(working_directory_arg.clone()),
+        )
+        .subcommand(SubCommand::with_name("daemon").about(
+            "Caches path compression and repository status for the other subcommands, \
+             listening on $XDG_RUNTIME_DIR/clanker.sock",
+        ))
         .get_matches();
 
     if let Some(matches) = matches.subcommand_matches("prompt") {
         let unprivileged_cursor = matches.value_of("unprivileged_cursor").unwrap();
         let privileged_cursor = matches.value_of("privileged_cursor").unwrap();
-        let compressed_working_directory = compressed_working_directory(matches);
-
-        if matches.is_present("no_username_hostname") {
-            if is_root() {
-                print!(
-                    "{}{} ",
-                    compressed_working_directory.red(),
-                    privileged_cursor
-                );
-            } else {
-                print!(
-                    "{}{} ",
-                    compressed_working_directory.green(),
-                    unprivileged_cursor
-                );
-            }
+        let cursor = if is_root() {
+            privileged_cursor
         } else {
-            let (username, is_root) = username_and_is_root();
-            let hostname = hostname();
-
-            if is_root {
-                print!(
-                    "{}@{} {}{} ",
-                    username,
-                    hostname,
-                    compressed_working_directory.red(),
-                    privileged_cursor
-                );
-            } else {
-                print!(
-                    "{}@{} {}{} ",
-                    username,
-                    hostname,
-                    compressed_working_directory.green(),
-                    unprivileged_cursor
-                );
-            }
+            unprivileged_cursor
+        };
+
+        let username_hostname = if matches.is_present("no_username_hostname") {
+            String::new()
+        } else {
+            format!("{}@{} ", username(), hostname())
+        };
+
+        // everything else on the line, including the space after the cursor
+        let max_width = max_width(
+            matches,
+            compress::width(&username_hostname) + compress::width(cursor) + 1,
+        );
+        let compressed_working_directory = compressed_working_directory(matches, max_width);
+
+        if is_root() {
+            print!(
+                "{}{}{} ",
+                username_hostname,
+                compressed_working_directory.red(),
+                cursor
+            );
+        } else {
+            print!(
+                "{}{}{} ",
+                username_hostname,
+                compressed_working_directory.green(),
+                cursor
+            );
         }
     } else if let Some(matches) = matches.subcommand_matches("right-prompt") {
         let return_code: i32 = matches.value_of("return_code").unwrap().parse().unwrap();
 
-        if let Some(head) = repo_head() {
+        if let Some(head) = vcs_head(&git_options(matches)) {
             if return_code != 0 {
                 print!("{} ({})", return_code.red(), head);
             } else {
@@ -193,13 +445,54 @@ fn main() {
             print!("{}", return_code.red());
         }
     } else if let Some(matches) = matches.subcommand_matches("title") {
-        let compressed_working_directory = compressed_working_directory(matches);
+        let compressed_working_directory = compressed_working_directory(matches, None);
 
         if let Some(current) = matches.value_of("current") {
             print!("{} {}", current, compressed_working_directory)
         } else {
             print!("{}", compressed_working_directory);
         }
+    } else if let Some(matches) = matches.subcommand_matches("expand") {
+        let path = matches.value_of("path").unwrap();
+        let working_directory = matches
+            .value_of_os("working_directory")
+            .map(PathBuf::from)
+            .or_else(|| env::current_dir().ok())
+            .unwrap_or_else(|| PathBuf::from("/"));
+
+        match compress::expand(path, &working_directory, &aliases(matches)) {
+            Ok(expanded) => println!("{}", expanded.display()),
+            Err(ExpandError::NotFound(dir, component)) => {
+                eprintln!(
+                    "clanker: no directory in {} starts with {}",
+                    dir.display(),
+                    component
+                );
+                process::exit(1);
+            }
+            Err(ExpandError::Ambiguous(candidates)) => {
+                eprintln!("clanker: {} is ambiguous", path);
+
+                for candidate in candidates {
+                    println!("{}", candidate.display());
+                }
+
+                process::exit(2);
+            }
+        }
+    } else if matches.subcommand_matches("daemon").is_some() {
+        let socket_path = match daemon::socket_path() {
+            Some(p) => p,
+            None => {
+                eprintln!("clanker: XDG_RUNTIME_DIR is not set");
+                process::exit(1);
+            }
+        };
+
+        if let Err(e) = daemon::serve(&socket_path) {
+            eprintln!("clanker: {}", e);
+            process::exit(1);
+        }
     } else {
         unreachable!();
     }
@@ -209,14 +502,14 @@ fn is_root() -> bool {
     unsafe { libc::geteuid() == 0 }
 }
 
-fn username_and_is_root() -> (String, bool) {
+fn username() -> String {
     let euid = unsafe { libc::geteuid() };
     let passwd = unsafe { libc::getpwuid(euid) };
     assert!(!passwd.is_null());
 
     let username = unsafe { CStr::from_ptr((*passwd).pw_name) };
 
-    (username.to_string_lossy().into_owned(), euid == 0)
+    username.to_string_lossy().into_owned()
 }
 
 fn hostname() -> String {
@@ -232,7 +525,23 @@ fn hostname() -> String {
     hostname.to_string_lossy().into_owned()
 }
 
-fn compressed_working_directory(matches: &ArgMatches) -> String {
+// --max-width, else the terminal's width less what the rest of the prompt takes up
+fn max_width(matches: &ArgMatches, rest_width: usize) -> Option<usize> {
+    if !matches.is_present("max_width") {
+        return None;
+    }
+
+    match matches.value_of("max_width") {
+        Some(max_width) => max_width.parse().ok(),
+        None => {
+            let columns: usize = env::var("COLUMNS").ok()?.parse().ok()?;
+
+            Some(columns.saturating_sub(rest_width))
+        }
+    }
+}
+
+fn compressed_working_directory(matches: &ArgMatches, max_width: Option<usize>) -> String {
     let min_home_dir_uid = matches
         .value_of("min_home_dir_uid")
         .unwrap()
@@ -244,63 +553,583 @@ fn compressed_working_directory(matches: &ArgMatches) -> String {
         .parse()
         .unwrap();
 
-    if let Some(dir) = matches.value_of_os("working_directory") {
-        compress::compress(dir.as_ref(), min_home_dir_uid, max_home_dir_uid).ok()
-    } else if let Ok(dir) = env::current_dir() {
-        compress::compress(&dir, min_home_dir_uid, max_home_dir_uid).ok()
-    } else {
-        None
+    let mut strategy = compression_strategy(matches);
+    let anchors = anchors(matches);
+
+    let dir = match matches.value_of_os("working_directory") {
+        Some(dir) => PathBuf::from(dir),
+        None => match env::current_dir() {
+            Ok(dir) => dir,
+            Err(_) => return "?".to_string(),
+        },
+    };
+
+    if matches.is_present("repo_relative") {
+        if let Some(compressed) =
+            compressed_within_repository(&dir, max_width, strategy.as_mut(), anchors.as_ref())
+        {
+            return compressed;
+        }
+    }
+
+    compress::compress(
+        &dir,
+        min_home_dir_uid,
+        max_home_dir_uid,
+        &aliases(matches),
+        max_width,
+        strategy.as_mut(),
+        anchors.as_ref(),
+    )
+    .unwrap_or_else(|_| "?".to_string())
+}
+
+fn compression_strategy(matches: &ArgMatches) -> Box<dyn Strategy> {
+    let length = matches
+        .value_of("compression_length")
+        .unwrap()
+        .parse()
+        .unwrap();
+
+    match matches.value_of("compression").unwrap() {
+        "fixed" => Box::new(FixedLength(length)),
+        "first" => Box::new(FirstGrapheme),
+        "last" => Box::new(LastComponents(length)),
+        "none" => Box::new(NoCompression),
+        _ => {
+            let mut client = daemon::Client::connect();
+
+            Box::new(move |dir: &Path, component: &OsStr| {
+                if let Some(abbreviation) =
+                    client.as_mut().and_then(|c| c.abbreviate(dir, component))
+                {
+                    return abbreviation;
+                }
+
+                client = None; // don't keep waiting on a daemon that stopped answering
+
+                compress::abbreviate(dir, component)
+            })
+        }
     }
-    .unwrap_or_else(|| "?".to_string())
 }
 
-fn repo_head() -> Option<String> {
-    let is_dirty_thread = thread::spawn(repository_is_dirty);
+fn aliases(matches: &ArgMatches) -> Vec<Alias> {
+    matches
+        .values_of("alias")
+        .map(|aliases| aliases.map(|a| a.parse().unwrap()).collect())
+        .unwrap_or_default()
+}
 
-    let repo = Repository::open_from_env()?;
-    let identifier = identify_head(&repo)?;
+fn anchors(matches: &ArgMatches) -> Option<Anchors> {
+    let bold = matches.is_present("bold_anchors");
 
-    if is_dirty_thread.join().ok().unwrap_or(false) {
-        Some(format!("{} *", identifier))
-    } else {
-        Some(identifier)
+    if !bold && !matches.is_present("anchors") && matches.occurrences_of("anchor_markers") == 0 {
+        return None;
+    }
+
+    let markers = matches
+        .values_of_os("anchor_markers")
+        .unwrap()
+        .map(OsString::from)
+        .collect();
+
+    Some(Anchors::new(markers, bold))
+}
+
+// e.g. clanker/src/c/gct, with the repository's name in bold
+fn compressed_within_repository(
+    dir: &Path,
+    max_width: Option<usize>,
+    strategy: &mut dyn Strategy,
+    anchors: Option<&Anchors>,
+) -> Option<String> {
+    let repo = Repository::discover(dir)?;
+    let workdir = repo.workdir()?;
+    let name = workdir.file_name()?.to_string_lossy().bold().to_string();
+
+    // $PWD keeps the symlinks that libgit2 resolves
+    compress::compress_within(dir, workdir, name.clone(), max_width, strategy, anchors).or_else(
+        || {
+            let dir = dir.canonicalize().ok()?;
+
+            compress::compress_within(&dir, workdir, name, max_width, strategy, anchors)
+        },
+    )
+}
+
+struct GitOptions {
+    describe: bool,
+    max_tags: Option<usize>,
+    untracked: Untracked,
+    status_timeout: Option<Duration>,
+    dirty_only: bool,
+    submodules: bool,
+    commit_age: bool,
+    check_ownership: bool,
+    show_upstream: bool,
+    max_branch_width: Option<usize>,
+    truncation: Truncation,
+}
+
+fn git_options(matches: &ArgMatches) -> GitOptions {
+    GitOptions {
+        describe: matches.is_present("describe"),
+        max_tags: matches.value_of("max_tags").map(|m| m.parse().unwrap()),
+        untracked: matches
+            .value_of("untracked_files")
+            .unwrap()
+            .parse()
+            .unwrap(),
+        status_timeout: matches
+            .value_of("status_timeout")
+            .map(|t| Duration::from_millis(t.parse().unwrap())),
+        dirty_only: !matches.is_present("detailed_status"),
+        submodules: matches.is_present("submodules"),
+        commit_age: matches.is_present("commit_age"),
+        check_ownership: !matches.is_present("allow_dubious_ownership"),
+        show_upstream: matches.is_present("show_upstream"),
+        max_branch_width: matches
+            .value_of("max_branch_width")
+            .map(|m| m.parse().unwrap()),
+        truncation: matches.value_of("truncate").unwrap().parse().unwrap(),
     }
 }
 
-fn identify_head(repo: &Repository) -> Option<String> {
-    let head = repo.head()?;
+fn vcs_head(options: &GitOptions) -> Option<String> {
+    let current_dir = env::current_dir().ok()?;
+
+    match vcs::discover(&current_dir) {
+        Some(vcs) if !vcs.is_git() => vcs_segment(vcs.as_ref(), &current_dir, options),
+        // git is opened again from the environment, since $GIT_DIR can point anywhere. that
+        // also covers repositories libgit2 can't open, which aren't found here
+        _ => repo_head(options),
+    }
+}
+
+// everything but git, which has plenty more to show
+fn vcs_segment(vcs: &dyn Vcs, current_dir: &Path, options: &GitOptions) -> Option<String> {
+    let deadline = options.status_timeout.map(|t| Instant::now() + t);
+    let (sender, receiver) = mpsc::channel();
+    let current_dir = current_dir.to_path_buf();
+
+    // checking for modifications looks at every tracked file, so it gets the same deadline as
+    // git's status. it finds the checkout again for itself, just like the git status walk
+    thread::spawn(move || {
+        let _ = sender.send(vcs::discover(&current_dir).and_then(|v| v.is_modified()));
+    });
+
+    let mut segment = shorten_branch_name(&vcs.identify()?, options);
+
+    if let Some(operation) = vcs.operation() {
+        segment = format!("{} {}", operation.yellow(), segment);
+    }
 
+    match wait_for_status(&receiver, deadline) {
+        Ok(Some(true)) => segment.push_str(" *"),
+        Err(RecvTimeoutError::Timeout) => segment.push_str(" …"),
+        _ => (),
+    }
+
+    Some(segment)
+}
+
+// without a deadline, waits as long as it takes
+fn wait_for_status<T>(
+    receiver: &Receiver<T>,
+    deadline: Option<Instant>,
+) -> Result<T, RecvTimeoutError> {
+    match deadline {
+        Some(deadline) => receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())),
+        None => receiver.recv().map_err(RecvTimeoutError::from),
+    }
+}
+
+fn repo_head(options: &GitOptions) -> Option<String> {
+    let deadline = options.status_timeout.map(|t| Instant::now() + t);
+
+    let repo = match Repository::open_from_env(options.check_ownership) {
+        Ok(r) => r,
+        // git itself would refuse to work here, so don't pretend everything is fine
+        Err(OpenError::DubiousOwnership) => return Some("⚠ dubious ownership".red().to_string()),
+        Err(OpenError::NotFound) => return unsupported_repo_head(options),
+    };
+
+    let (untracked, dirty_only, submodules) =
+        (options.untracked, options.dirty_only, options.submodules);
+    let (sender, receiver) = mpsc::channel();
+
+    // the status walk never writes to the repository, so if it misses the deadline it's safe
+    // to leave it behind and let it die with the process
+    thread::spawn(move || {
+        let _ = sender.send(repository_status(untracked, dirty_only, submodules));
+    });
+    let head = repo.head();
+    let is_rebasing_branch = repo.rebase_progress().and_then(|p| p.branch_name).is_some();
+
+    // git has options of its own for naming branches and detached heads. the branch being
+    // rebased and the one an empty repository will commit to are named like any other VCS would
+    let mut segment = match &head {
+        Some(head) if !is_rebasing_branch => identify_head(&repo, head, options)?,
+        _ => shorten_branch_name(&repo.identify()?, options),
+    };
+
+    // nothing has been committed yet, but we know which branch it will go on
+    if repo.is_head_unborn() {
+        write!(segment, " {}", "empty".yellow()).unwrap();
+    }
+
+    if let Some(operation) = repo.operation() {
+        segment = format!("{} {}", operation.yellow(), segment);
+    }
+
+    if repo.is_bare() {
+        write!(segment, " {}", "bare".yellow()).unwrap();
+    } else if is_inside_git_dir(&repo) {
+        write!(segment, " {}", "GIT_DIR!".yellow()).unwrap();
+    }
+
+    // log and blame only see part of the history
+    if repo.is_shallow() {
+        write!(segment, " {}", "shallow".yellow()).unwrap();
+    }
+
+    // read once for everything that depends on it
+    if let Some(config) = repo.config() {
+        if repo.is_partial_clone(&config) {
+            write!(segment, " {}", "partial".blue()).unwrap();
+        }
+
+        if repo.is_sparse(&config) {
+            write!(segment, " {}", "sparse".blue()).unwrap();
+        }
+
+        match repo.lfs(&config) {
+            Some(Lfs::Smudged) => write!(segment, " {}", "lfs".blue()).unwrap(),
+            Some(Lfs::Unsmudged) => write!(segment, " {}", "lfs!".yellow()).unwrap(),
+            None => (),
+        }
+    }
+
+    // every worktree can have the same branch names, so say which checkout this is
+    if repo.is_worktree() {
+        if let Some(name) = repo.worktree_name() {
+            segment = format!("{} {}", format!("⎇{}", name).blue(), segment);
+        }
+    }
+
+    // finding the superproject means opening another repository and reading its index
+    if let Some((superproject, differs)) = head.as_ref().and_then(|h| superproject(&repo, h)) {
+        segment = format!("{}› {}", superproject, segment);
+
+        // the superproject would record a different commit if it were committed now
+        if differs {
+            write!(segment, " {}", "≠".yellow()).unwrap();
+        }
+    }
+
+    if let Some((ahead, behind)) = head.as_ref().and_then(|h| upstream_ahead_behind(&repo, h)) {
+        if ahead > 0 {
+            write!(segment, " ↑{}", ahead).unwrap();
+        }
+
+        if behind > 0 {
+            write!(segment, " ↓{}", behind).unwrap();
+        }
+    }
+
+    match wait_for_status(&receiver, deadline) {
+        Ok(Some(status)) if !status.is_empty() => {
+            segment.push(' ');
+            segment.push_str(&status);
+        }
+        Err(RecvTimeoutError::Timeout) => segment.push_str(" …"),
+        _ => (),
+    }
+
+    match repo.stash_count() {
+        Some(0) | None => (),
+        Some(count) => write!(segment, " ${}", count).unwrap(),
+    }
+
+    if options.commit_age {
+        if let Some(age) = head
+            .and_then(|h| h.peel_to_commit())
+            .and_then(|c| commit_age(c.time()))
+        {
+            write!(segment, " {}", age).unwrap();
+        }
+    }
+
+    Some(segment)
+}
+
+// libgit2 can't open partial clones at all, so they only get what can be read from .git directly
+fn unsupported_repo_head(options: &GitOptions) -> Option<String> {
+    let current_dir = env::current_dir().ok()?;
+
+    let repo = match UnsupportedRepository::discover(&current_dir, options.check_ownership) {
+        Ok(r) => r,
+        Err(OpenError::DubiousOwnership) => return Some("⚠ dubious ownership".red().to_string()),
+        Err(OpenError::NotFound) => return None,
+    };
+
+    let mut segment = match repo.branch_name() {
+        Some(name) => shorten_branch_name(name, options),
+        None => repo.short_id()?.to_string(),
+    };
+
+    if repo.is_shallow() {
+        write!(segment, " {}", "shallow".yellow()).unwrap();
+    }
+
+    if repo.is_partial_clone() {
+        write!(segment, " {}", "partial".blue()).unwrap();
+    }
+
+    Some(segment)
+}
+
+// git still finds the repository from inside .git, but there's no working tree to speak of
+fn is_inside_git_dir(repo: &Repository) -> bool {
+    match (env::current_dir(), repo.path().canonicalize()) {
+        (Ok(current_dir), Ok(git_dir)) => current_dir.starts_with(git_dir),
+        _ => false,
+    }
+}
+
+fn identify_head(repo: &Repository, head: &Reference, options: &GitOptions) -> Option<String> {
     if let Some(name) = head.branch_name() {
-        Some(name.to_string_lossy().into_owned())
+        let name = name.to_string_lossy();
+
+        let shortened = shorten_branch_name(&name, options);
+
+        if options.show_upstream {
+            if let Some(upstream) = renamed_upstream(head, &name) {
+                return Some(format!("{}→{}", shortened, upstream));
+            }
+        }
+
+        Some(shortened)
+    } else if options.describe {
+        head.peel_to_commit()?.describe()
     } else {
         let head_commit = head.peel_to_commit()?; // this had better point to a commit...
-        let tags = repo.tags_pointing_to(&head_commit).unwrap_or_else(Vec::new);
+
+        // look for one extra tag so we know whether any were left out
+        let limit = options.max_tags.map(|m| m + 1);
+        let mut tags = repo
+            .tags_pointing_to(&head_commit, limit)
+            .unwrap_or_default();
+
+        if let Some(max_tags) = options.max_tags {
+            if tags.len() > max_tags {
+                tags.truncate(max_tags);
+                tags.push("…".to_string());
+            }
+        }
 
         if tags.is_empty() {
             head_commit.as_object().short_id()
         } else {
-            let tag_names: Vec<_> = tags.iter().map(|n| n.to_string_lossy()).collect();
-
-            Some(tag_names.join("\\"))
+            Some(tags.join("\\"))
         }
     }
 }
 
-fn repository_is_dirty() -> bool {
-    // this is much faster than checking for the first diff and then aborting
-    // difference on my computer was from 3s (hand rolled libgit2, abort after first diff) to
-    // 660 ms when using the future-style computation here
-    Command::new("git")
-        .arg("status")
-        .arg("--porcelain")
-        .output()
-        .ok()
-        .map(|output| {
-            if !output.status.success() {
-                return false;
+fn shorten_branch_name(name: &str, options: &GitOptions) -> String {
+    match options.max_branch_width {
+        Some(max_width) => truncate::truncate(name, max_width, options.truncation),
+        None => name.to_string(),
+    }
+}
+
+// the upstream's shorthand, unless its branch has the same name as the local one
+fn renamed_upstream(head: &Reference, local_name: &str) -> Option<String> {
+    let upstream = head.upstream()?;
+    let shorthand = upstream.shorthand().to_string_lossy();
+
+    // remote names can contain slashes, so strip the actual remote rather than guess
+    let remote_prefix = head.upstream_remote().map(|r| format!("{}/", r));
+    let upstream_name = remote_prefix
+        .as_ref()
+        .and_then(|p| shorthand.strip_prefix(p.as_str()))
+        .unwrap_or(&shorthand);
+
+    if upstream_name == local_name {
+        None
+    } else {
+        Some(shorthand.into_owned())
+    }
+}
+
+// the name of the superproject if repo is one of its submodules, and whether HEAD differs from
+// the commit the superproject has recorded for it
+fn superproject(repo: &Repository, head: &Reference) -> Option<(String, bool)> {
+    // linked worktrees and unrelated repositories that happen to be nested in another's
+    // working directory aren't found as submodules
+    let superproject = repo.enclosing()?;
+    let superproject_workdir = superproject.workdir()?;
+    let path = repo.workdir()?.strip_prefix(superproject_workdir).ok()?;
+    let submodule = superproject.find_submodule(path)?;
+
+    let name = superproject_workdir
+        .file_name()?
+        .to_string_lossy()
+        .into_owned();
+    let differs = match (submodule.index_id(), head.target()) {
+        (Some(recorded), Some(current)) => recorded != current,
+        _ => false,
+    };
+
+    Some((name, differs))
+}
+
+fn commit_age(commit_time: i64) -> Option<String> {
+    const MINUTE: u64 = 60;
+    const HOUR: u64 = 60 * MINUTE;
+    const DAY: u64 = 24 * HOUR;
+    const WEEK: u64 = 7 * DAY;
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs() as i64;
+
+    // commits from the future happen when clocks disagree
+    let age = cmp::max(now - commit_time, 0) as u64;
+
+    let formatted = if age < MINUTE {
+        format!("{}s", age)
+    } else if age < HOUR {
+        format!("{}m", age / MINUTE)
+    } else if age < DAY {
+        format!("{}h", age / HOUR)
+    } else {
+        format!("{}d", age / DAY)
+    };
+
+    let colored = if age < DAY {
+        formatted.green().to_string()
+    } else if age < WEEK {
+        formatted.yellow().to_string()
+    } else {
+        formatted.red().to_string()
+    };
+
+    Some(colored)
+}
+
+fn upstream_ahead_behind(repo: &Repository, head: &Reference) -> Option<(usize, usize)> {
+    // detached heads and branches that don't track anything have no upstream
+    let upstream = head.upstream()?;
+
+    repo.ahead_behind(head.target()?, upstream.target()?)
+}
+
+fn repository_status(untracked: Untracked, dirty_only: bool, submodules: bool) -> Option<String> {
+    // libgit2 handles aren't thread safe, so the status walk gets its own. ownership was
+    // already checked when the first one was opened
+    let repo = Repository::open_from_env(false).ok()?;
+
+    let cached = repo
+        .workdir()
+        .and_then(|workdir| daemon::Client::connect()?.tree_status(workdir, untracked, dirty_only));
+
+    let mut status = match cached.or_else(|| daemon::tree_status(&repo, untracked, dirty_only))? {
+        TreeStatus::Dirty(true) => "*".to_string(),
+        TreeStatus::Dirty(false) => String::new(),
+        TreeStatus::Summary(summary) => render_status(&summary),
+    };
+
+    // reads the status of every submodule, so it's under the same deadline
+    if submodules {
+        match repo.dirty_submodule_count() {
+            Some(0) | None => (),
+            Some(count) => {
+                if !status.is_empty() {
+                    status.push(' ');
+                }
+
+                write!(status, "{}", format!("§{}", count).yellow()).unwrap();
             }
+        }
+    }
+
+    Some(status)
+}
+
+fn render_status(summary: &StatusSummary) -> String {
+    let mut rendered = Vec::new();
+
+    if summary.conflicted > 0 {
+        rendered.push(format!("={}", summary.conflicted).red().to_string());
+    }
+
+    if summary.staged > 0 {
+        rendered.push(format!("+{}", summary.staged).green().to_string());
+    }
+
+    if summary.renamed > 0 {
+        rendered.push(format!("»{}", summary.renamed).magenta().to_string());
+    }
 
-            !output.stdout.is_empty()
-        })
-        .unwrap_or(false)
+    if summary.modified > 0 {
+        rendered.push(format!("!{}", summary.modified).yellow().to_string());
+    }
+
+    if summary.deleted > 0 {
+        rendered.push(format!("✘{}", summary.deleted).red().to_string());
+    }
+
+    if summary.untracked > 0 {
+        rendered.push(format!("?{}", summary.untracked).blue().to_string());
+    }
+
+    rendered.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_status() {
+        assert_eq!(super::render_status(&StatusSummary::default()), "");
+
+        let summary = StatusSummary {
+            staged: 1,
+            modified: 2,
+            untracked: 3,
+            ..StatusSummary::default()
+        };
+
+        assert_eq!(
+            super::render_status(&summary),
+            format!("{} {} {}", "+1".green(), "!2".yellow(), "?3".blue())
+        );
+    }
+
+    #[test]
+    fn commit_age() {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+
+        assert_eq!(
+            super::commit_age(now - 2 * 60 * 60 - 30).unwrap(),
+            "2h".green().to_string()
+        );
+        assert_eq!(
+            super::commit_age(now - 3 * 24 * 60 * 60).unwrap(),
+            "3d".yellow().to_string()
+        );
+        assert_eq!(
+            super::commit_age(now - 30 * 24 * 60 * 60).unwrap(),
+            "30d".red().to_string()
+        );
+        // clock skew shouldn't make a commit look older than it is
+        assert_eq!(
+            super::commit_age(now + 60 * 60).unwrap(),
+            "0s".green().to_string()
+        );
+    }
 }
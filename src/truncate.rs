@@ -0,0 +1,218 @@
+// Copyright (C) 2020 Gregory Meyer
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::{cmp, ops::Range, str::FromStr};
+
+use unicode_segmentation::UnicodeSegmentation;
+
+const ELLIPSIS: &str = "…";
+
+// which part of a name survives truncation
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Truncation {
+    Prefix,
+    Suffix,
+    Middle,
+}
+
+impl Truncation {
+    // how many graphemes to keep from each end, given at most head_max from the start and
+    // tail_max from the end
+    fn split(self, budget: usize, head_max: usize, tail_max: usize) -> (usize, usize) {
+        match self {
+            Truncation::Prefix => {
+                let head = cmp::min(budget, head_max);
+
+                (head, cmp::min(budget - head, tail_max))
+            }
+            Truncation::Suffix => {
+                let tail = cmp::min(budget, tail_max);
+
+                (cmp::min(budget - tail, head_max), tail)
+            }
+            Truncation::Middle => {
+                let head = cmp::min(budget - budget / 2, head_max);
+                let tail = cmp::min(budget - head, tail_max);
+
+                // give back whatever the tail couldn't use
+                (cmp::min(budget - tail, head_max), tail)
+            }
+        }
+    }
+}
+
+impl FromStr for Truncation {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Truncation, ()> {
+        match s {
+            "prefix" => Ok(Truncation::Prefix),
+            "suffix" => Ok(Truncation::Suffix),
+            "middle" => Ok(Truncation::Middle),
+            _ => Err(()),
+        }
+    }
+}
+
+// shortens name to at most max_width grapheme clusters, marking what was cut with '…'. a ticket
+// ID like PROJ-12345 is kept whole if there's room for it
+pub fn truncate(name: &str, max_width: usize, truncation: Truncation) -> String {
+    let graphemes: Vec<&str> = name.graphemes(true).collect();
+    let len = graphemes.len();
+
+    if len <= max_width || max_width == 0 {
+        return name.to_string();
+    }
+
+    let (head, tail) = truncation.split(max_width - 1, len, len);
+
+    let ticket = match ticket_id(&graphemes) {
+        // already kept without any help
+        Some(t) if t.end <= head || t.start >= len - tail => None,
+        // the ticket and an ellipsis on either side
+        Some(t) if t.len() + 2 <= max_width => Some(t),
+        _ => None,
+    };
+
+    let mut truncated = String::new();
+
+    if let Some(ticket) = ticket {
+        let budget = max_width - ticket.len() - 2;
+        let (head, tail) = truncation.split(budget, ticket.start, len - ticket.end);
+
+        truncated.extend(graphemes[..head].iter().copied());
+
+        if head < ticket.start {
+            truncated.push_str(ELLIPSIS);
+        }
+
+        truncated.extend(graphemes[ticket.clone()].iter().copied());
+
+        if len - tail > ticket.end {
+            truncated.push_str(ELLIPSIS);
+        }
+
+        truncated.extend(graphemes[len - tail..].iter().copied());
+    } else {
+        truncated.extend(graphemes[..head].iter().copied());
+        truncated.push_str(ELLIPSIS);
+        truncated.extend(graphemes[len - tail..].iter().copied());
+    }
+
+    truncated
+}
+
+// the first word of uppercase letters and digits, starting with a letter, followed by a dash and
+// a number, like PROJ-12345
+fn ticket_id(graphemes: &[&str]) -> Option<Range<usize>> {
+    let is = |i: usize, f: fn(&u8) -> bool| {
+        graphemes
+            .get(i)
+            .map(|g| g.len() == 1 && f(&g.as_bytes()[0]))
+            .unwrap_or(false)
+    };
+    let is_key = |b: &u8| b.is_ascii_uppercase() || b.is_ascii_digit();
+
+    for start in 0..graphemes.len() {
+        if !is(start, u8::is_ascii_uppercase)
+            || (start > 0 && is(start - 1, u8::is_ascii_alphanumeric))
+        {
+            continue;
+        }
+
+        let mut end = start + 1;
+
+        while is(end, is_key) {
+            end += 1;
+        }
+
+        if end - start < 2 || !is(end, |&b| b == b'-') || !is(end + 1, u8::is_ascii_digit) {
+            continue;
+        }
+
+        end += 1;
+
+        while is(end, u8::is_ascii_digit) {
+            end += 1;
+        }
+
+        return Some(start..end);
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BRANCH: &str = "feature/PROJ-12345-rework-the-authentication-middleware";
+
+    #[test]
+    fn short_names_are_untouched() {
+        assert_eq!(truncate("main", 4, Truncation::Middle), "main");
+        assert_eq!(truncate("main", 10, Truncation::Prefix), "main");
+    }
+
+    #[test]
+    fn strategies() {
+        assert_eq!(truncate("abcdefghij", 5, Truncation::Prefix), "abcd…");
+        assert_eq!(truncate("abcdefghij", 5, Truncation::Suffix), "…ghij");
+        assert_eq!(truncate("abcdefghij", 5, Truncation::Middle), "ab…ij");
+        assert_eq!(truncate("abcdefghij", 6, Truncation::Middle), "abc…ij");
+    }
+
+    #[test]
+    fn keeps_ticket_id() {
+        assert_eq!(
+            truncate(BRANCH, 20, Truncation::Prefix),
+            "feature/PROJ-12345-…"
+        );
+        assert_eq!(
+            truncate(BRANCH, 20, Truncation::Suffix),
+            "…PROJ-12345…ddleware"
+        );
+        assert_eq!(
+            truncate(BRANCH, 20, Truncation::Middle),
+            "feat…PROJ-12345…ware"
+        );
+        assert_eq!(truncate(BRANCH, 12, Truncation::Middle), "…PROJ-12345…");
+
+        // no room for the ticket, so it's truncated like anything else
+        assert_eq!(truncate(BRANCH, 8, Truncation::Middle), "feat…are");
+    }
+
+    #[test]
+    fn counts_graphemes() {
+        let name = "e\u{301}e\u{301}e\u{301}e\u{301}";
+
+        assert_eq!(truncate(name, 3, Truncation::Prefix), "e\u{301}e\u{301}…");
+    }
+
+    #[test]
+    fn ticket_ids() {
+        let find = |s: &str| {
+            let graphemes: Vec<&str> = s.graphemes(true).collect();
+
+            ticket_id(&graphemes).map(|r| graphemes[r].concat())
+        };
+
+        assert_eq!(find(BRANCH), Some("PROJ-12345".to_string()));
+        assert_eq!(find("fix/AB2-7"), Some("AB2-7".to_string()));
+        assert_eq!(find("release-2.x"), None);
+        assert_eq!(find("wip/A-1"), None);
+        assert_eq!(find("xPROJ-1"), None);
+    }
+}
@@ -0,0 +1,78 @@
+// Copyright (C) 2020 Gregory Meyer
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::path::Path;
+
+use crate::git::Repository;
+
+mod fossil;
+mod git;
+mod hg;
+mod jj;
+mod sqlite;
+
+use fossil::Fossil;
+use hg::Mercurial;
+use jj::Jujutsu;
+
+// what the right prompt needs to know about any kind of repository
+pub trait Vcs {
+    // the branch, bookmark or revision the working copy is based on
+    fn identify(&self) -> Option<String>;
+
+    // None if it can't be determined
+    fn is_modified(&self) -> Option<bool>;
+
+    // an unfinished merge, rebase or the like, e.g. "MERGING"
+    fn operation(&self) -> Option<String>;
+
+    // git repositories have much more to show than this, so the right prompt gives them their
+    // own segment
+    fn is_git(&self) -> bool {
+        false
+    }
+}
+
+// the innermost repository containing dir
+pub fn discover(dir: &Path) -> Option<Box<dyn Vcs>> {
+    for ancestor in dir.ancestors() {
+        // colocated jj repositories have a .git too, but jj is the one in charge of them
+        if ancestor.join(".jj").is_dir() {
+            return Jujutsu::open(ancestor).map(|j| Box::new(j) as Box<dyn Vcs>);
+        }
+
+        if ancestor.join(".git").exists() {
+            return Repository::discover(ancestor).map(|r| Box::new(r) as Box<dyn Vcs>);
+        }
+
+        if ancestor.join(".hg").is_dir() {
+            return Some(Box::new(Mercurial::open(ancestor)));
+        }
+
+        for checkout in [".fslckout", "_FOSSIL_"].iter() {
+            let checkout = ancestor.join(checkout);
+
+            if checkout.is_file() {
+                return Fossil::open(ancestor, &checkout).map(|f| Box::new(f) as Box<dyn Vcs>);
+            }
+        }
+    }
+
+    None
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -0,0 +1,141 @@
+// Copyright (C) 2020 Gregory Meyer
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::{
+    fs,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+use super::{
+    sqlite::{Database, Table},
+    Vcs,
+};
+
+// how much of a check-in hash to show when it isn't on a branch
+const SHORT_HASH_LEN: usize = 10;
+
+// a Fossil checkout, read from its checkout database and the repository it belongs to
+pub struct Fossil {
+    root: PathBuf,
+    checkout: Database,
+    checkout_rid: i64,
+    vvar: Table,
+}
+
+impl Fossil {
+    pub fn open(root: &Path, checkout: &Path) -> Option<Fossil> {
+        let checkout = Database::open(checkout)?;
+        let vvar = checkout.table("vvar")?;
+
+        let checkout_rid = Fossil::var(&vvar, "checkout")?.parse().ok()?;
+
+        Some(Fossil {
+            root: root.to_path_buf(),
+            checkout,
+            checkout_rid,
+            vvar,
+        })
+    }
+
+    fn var<'t>(vvar: &'t Table, name: &str) -> Option<&'t str> {
+        vvar.rows()
+            .find(|r| r.get("name").as_text() == Some(name))
+            .and_then(|r| r.get("value").as_text())
+    }
+
+    // the value of the branch tag on the current check-in, which lives in the repository.
+    // stops reading tag and tagxref at the first match, since tagxref grows with every check-in
+    fn branch(&self) -> Option<String> {
+        let repository = Database::open(Path::new(Fossil::var(&self.vvar, "repository")?))?;
+
+        let branch_tag = repository.find_map("tag", |r| {
+            if r.get("tagname").as_text() == Some("branch") {
+                r.get("tagid").as_integer()
+            } else {
+                None
+            }
+        })?;
+
+        // a tagtype of 0 means the tag was cancelled
+        repository.find_map("tagxref", |r| {
+            if r.get("tagid").as_integer() == Some(branch_tag)
+                && r.get("rid").as_integer() == Some(self.checkout_rid)
+                && r.get("tagtype")
+                    .as_integer()
+                    .map(|t| t > 0)
+                    .unwrap_or(false)
+            {
+                r.get("value").as_text().map(str::to_string)
+            } else {
+                None
+            }
+        })
+    }
+}
+
+impl Vcs for Fossil {
+    fn identify(&self) -> Option<String> {
+        self.branch().or_else(|| {
+            let hash = Fossil::var(&self.vvar, "checkout-hash")?;
+
+            Some(hash[..hash.len().min(SHORT_HASH_LEN)].to_string())
+        })
+    }
+
+    // fossil only records changes when it scans the checkout, so anything touched since then
+    // is assumed to be changed too
+    fn is_modified(&self) -> Option<bool> {
+        let vfile = self.checkout.table("vfile")?;
+
+        let is_modified = vfile
+            .rows()
+            .filter(|r| r.get("vid").as_integer() == Some(self.checkout_rid))
+            .any(|r| {
+                let flag = |column| r.get(column).as_integer().unwrap_or(0) != 0;
+
+                // a rid of 0 means the file was added
+                if flag("chnged") || flag("deleted") || !flag("rid") {
+                    return true;
+                }
+
+                let path = match r.get("pathname").as_text() {
+                    Some(p) => self.root.join(p),
+                    None => return true,
+                };
+
+                match fs::symlink_metadata(path) {
+                    Ok(metadata) => Some(metadata.mtime()) != r.get("mtime").as_integer(),
+                    Err(_) => true,
+                }
+            });
+
+        Some(is_modified)
+    }
+
+    fn operation(&self) -> Option<String> {
+        let vmerge = self.checkout.table("vmerge")?;
+        let id = vmerge.rows().next()?.get("id").as_integer()?;
+
+        // negative IDs are the other ways of pulling in changes
+        let operation = match id {
+            -1 => "CHERRY-PICKING",
+            -2 => "BACKING-OUT",
+            _ => "MERGING",
+        };
+
+        Some(operation.to_string())
+    }
+}
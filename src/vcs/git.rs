@@ -0,0 +1,70 @@
+// Copyright (C) 2020 Gregory Meyer
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use super::Vcs;
+use crate::git::{Repository, State, StatusOptions, Untracked};
+
+impl Vcs for Repository {
+    fn identify(&self) -> Option<String> {
+        // mid-rebase HEAD is detached, so the branch being rebased says more
+        if let Some(name) = self.rebase_progress().and_then(|p| p.branch_name) {
+            return Some(name);
+        }
+
+        let head = match self.head() {
+            Some(h) => h,
+            None => return self.unborn_branch_name(),
+        };
+
+        match head.branch_name() {
+            Some(name) => Some(name.to_string_lossy().into_owned()),
+            None => head.peel_to_commit()?.describe(),
+        }
+    }
+
+    fn is_modified(&self) -> Option<bool> {
+        let mut options = StatusOptions::new();
+        options.untracked(Untracked::Normal);
+
+        self.is_dirty(&options)
+    }
+
+    fn operation(&self) -> Option<String> {
+        let operation = match self.state() {
+            State::Clean => return None,
+            State::Merge => "MERGING",
+            State::Revert => "REVERTING",
+            State::CherryPick => "CHERRY-PICKING",
+            State::Bisect => "BISECTING",
+            State::Rebase => "REBASE",
+            State::RebaseInteractive => "REBASE-i",
+            State::RebaseMerge => "REBASE-m",
+            State::ApplyMailbox => "AM",
+            State::ApplyMailboxOrRebase => "AM/REBASE",
+        };
+
+        match self.rebase_progress() {
+            Some(progress) => Some(format!(
+                "{} {}/{}",
+                operation, progress.step, progress.total
+            )),
+            None => Some(operation.to_string()),
+        }
+    }
+
+    fn is_git(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,231 @@
+// Copyright (C) 2020 Gregory Meyer
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::{
+    convert::TryInto,
+    ffi::OsStr,
+    fs,
+    os::unix::{
+        ffi::OsStrExt,
+        fs::{MetadataExt, PermissionsExt},
+    },
+    path::{Path, PathBuf},
+};
+
+use super::Vcs;
+
+// a Mercurial working copy, read straight from .hg
+pub struct Mercurial {
+    root: PathBuf,
+    hg: PathBuf,
+}
+
+impl Mercurial {
+    pub fn open(root: &Path) -> Mercurial {
+        Mercurial {
+            root: root.to_path_buf(),
+            hg: root.join(".hg"),
+        }
+    }
+
+    fn read_trimmed(&self, name: &str) -> Option<String> {
+        let contents = fs::read_to_string(self.hg.join(name)).ok()?;
+        let trimmed = contents.trim();
+
+        if trimmed.is_empty() {
+            None
+        } else {
+            Some(trimmed.to_string())
+        }
+    }
+}
+
+impl Vcs for Mercurial {
+    fn identify(&self) -> Option<String> {
+        // no branch file means the default branch
+        self.read_trimmed("bookmarks.current")
+            .or_else(|| self.read_trimmed("branch"))
+            .or_else(|| Some("default".to_string()))
+    }
+
+    fn is_modified(&self) -> Option<bool> {
+        let requires = fs::read_to_string(self.hg.join("requires")).unwrap_or_default();
+
+        // dirstate-v2 is a different format altogether
+        if requires.lines().any(|r| r == "dirstate-v2") {
+            return None;
+        }
+
+        let dirstate = fs::read(self.hg.join("dirstate")).ok()?;
+        let dirstate = parse_dirstate(&dirstate)?;
+
+        // a second parent means a merge is waiting to be committed
+        Some(dirstate.is_merge || dirstate.entries.iter().any(|e| e.is_modified(&self.root)))
+    }
+
+    fn operation(&self) -> Option<String> {
+        const OPERATIONS: &[(&str, &str)] = &[
+            ("merge/state", "MERGING"),
+            ("rebasestate", "REBASING"),
+            ("histedit-state", "HISTEDIT"),
+            ("graftstate", "GRAFTING"),
+            ("shelvedstate", "UNSHELVING"),
+            ("updatestate", "UPDATING"),
+        ];
+
+        OPERATIONS
+            .iter()
+            .find(|(file, _)| self.hg.join(file).exists())
+            .map(|(_, operation)| operation.to_string())
+    }
+}
+
+struct Dirstate<'a> {
+    is_merge: bool,
+    entries: Vec<Entry<'a>>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Entry<'a> {
+    state: u8,
+    mode: i32,
+    size: i32,
+    mtime: i32,
+    path: &'a [u8],
+}
+
+impl<'a> Entry<'a> {
+    // without reading file contents, a changed size or modification time is taken to mean
+    // a changed file. untracked files aren't noticed, since that needs .hgignore
+    fn is_modified(&self, root: &Path) -> bool {
+        // anything but "normal" is added, removed or merged
+        if self.state != b'n' {
+            return true;
+        }
+
+        // -1 needs its contents checked and -2 comes from the other merge parent
+        if self.size < 0 {
+            return true;
+        }
+
+        match fs::symlink_metadata(root.join(OsStr::from_bytes(self.path))) {
+            Ok(metadata) => {
+                // sizes and times are stored in 31 bits
+                const RANGE_MASK: i64 = 0x7fff_ffff;
+
+                let is_executable = metadata.permissions().mode() & 0o100 != 0;
+
+                metadata.size() as i64 & RANGE_MASK != self.size as i64
+                    || metadata.mtime() & RANGE_MASK != self.mtime as i64
+                    || is_executable != (self.mode & 0o100 != 0)
+            }
+            // deleted
+            Err(_) => true,
+        }
+    }
+}
+
+// dirstate v1 is both parents' node IDs followed by one record per tracked file
+fn parse_dirstate(dirstate: &[u8]) -> Option<Dirstate<'_>> {
+    const NODE_LEN: usize = 20;
+    const HEADER_LEN: usize = 17;
+
+    let parents = dirstate.get(..2 * NODE_LEN)?;
+    let is_merge = parents[NODE_LEN..].iter().any(|&b| b != 0);
+    let mut entries = Vec::new();
+
+    let be_i32 = |bytes: &[u8]| i32::from_be_bytes(bytes.try_into().unwrap());
+    let mut rest = &dirstate[2 * NODE_LEN..];
+
+    while !rest.is_empty() {
+        let header = rest.get(..HEADER_LEN)?;
+        let len: usize = be_i32(&header[13..17]).try_into().ok()?;
+        let end = HEADER_LEN.checked_add(len)?;
+        let name = rest.get(HEADER_LEN..end)?;
+
+        // copies are recorded as "path\0source"
+        let path = name.split(|&b| b == 0).next().unwrap_or(name);
+
+        entries.push(Entry {
+            state: header[0],
+            mode: be_i32(&header[1..5]),
+            size: be_i32(&header[5..9]),
+            mtime: be_i32(&header[9..13]),
+            path,
+        });
+
+        rest = &rest[end..];
+    }
+
+    Some(Dirstate { is_merge, entries })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(state: u8, size: i32, name: &[u8]) -> Vec<u8> {
+        let mut entry = vec![state];
+        entry.extend_from_slice(&0o644i32.to_be_bytes());
+        entry.extend_from_slice(&size.to_be_bytes());
+        entry.extend_from_slice(&1234i32.to_be_bytes());
+        entry.extend_from_slice(&(name.len() as i32).to_be_bytes());
+        entry.extend_from_slice(name);
+
+        entry
+    }
+
+    #[test]
+    fn dirstate() {
+        let mut dirstate = vec![0xab; 20];
+        dirstate.extend_from_slice(&[0; 20]);
+        dirstate.extend(entry(b'n', 5, b"a.txt"));
+        dirstate.extend(entry(b'a', -1, b"b.txt\0a.txt"));
+
+        let parsed = parse_dirstate(&dirstate).unwrap();
+
+        assert!(!parsed.is_merge);
+        assert_eq!(
+            parsed.entries,
+            vec![
+                Entry {
+                    state: b'n',
+                    mode: 0o644,
+                    size: 5,
+                    mtime: 1234,
+                    path: b"a.txt",
+                },
+                Entry {
+                    state: b'a',
+                    mode: 0o644,
+                    size: -1,
+                    mtime: 1234,
+                    path: b"b.txt",
+                },
+            ]
+        );
+
+        // truncated in the middle of an entry
+        assert!(parse_dirstate(&dirstate[..dirstate.len() - 1]).is_none());
+
+        // a corrupt, negative length
+        let mut corrupt = dirstate.clone();
+        corrupt[40 + 13..40 + 17].copy_from_slice(&(-1i32).to_be_bytes());
+        assert!(parse_dirstate(&corrupt).is_none());
+
+        dirstate[20] = 0xcd;
+        assert!(parse_dirstate(&dirstate).unwrap().is_merge);
+    }
+}
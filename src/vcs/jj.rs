@@ -0,0 +1,253 @@
+// Copyright (C) 2020 Gregory Meyer
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    str,
+};
+
+use super::{hex, Vcs};
+
+// field numbers from jj's working_copy.proto and op_store.proto
+const CHECKOUT_OPERATION_ID: u32 = 2;
+const CHECKOUT_WORKSPACE_ID: u32 = 3;
+const OPERATION_VIEW_ID: u32 = 1;
+const VIEW_WC_COMMIT_ID_LEGACY: u32 = 2;
+const VIEW_BOOKMARKS: u32 = 5;
+const VIEW_WC_COMMIT_IDS: u32 = 8;
+const BOOKMARK_NAME: u32 = 1;
+const BOOKMARK_LOCAL_TARGET: u32 = 2;
+const REF_TARGET_COMMIT_ID: u32 = 1;
+const MAP_KEY: u32 = 1;
+const MAP_VALUE: u32 = 2;
+
+// how much of a commit ID to show when no bookmark points to it
+const SHORT_ID_LEN: usize = 8;
+
+// a Jujutsu workspace, read from the operation the working copy was last updated at
+pub struct Jujutsu {
+    wc_commit_id: Vec<u8>,
+    bookmarks: Vec<String>,
+}
+
+impl Jujutsu {
+    pub fn open(root: &Path) -> Option<Jujutsu> {
+        let jj = root.join(".jj");
+        let repo = repo_dir(&jj)?;
+
+        let checkout = fs::read(jj.join("working_copy").join("checkout")).ok()?;
+        let checkout = Message::parse(&checkout)?;
+        let operation_id = checkout.bytes(CHECKOUT_OPERATION_ID)?;
+        let workspace_id = checkout
+            .bytes(CHECKOUT_WORKSPACE_ID)
+            .and_then(|w| str::from_utf8(w).ok())
+            .unwrap_or("default");
+
+        let op_store = repo.join("op_store");
+
+        let operation = fs::read(op_store.join("operations").join(hex(operation_id))).ok()?;
+        let operation = Message::parse(&operation)?;
+        let view_id = operation.bytes(OPERATION_VIEW_ID)?;
+
+        let view = fs::read(op_store.join("views").join(hex(view_id))).ok()?;
+        let view = Message::parse(&view)?;
+
+        let wc_commit_id = view
+            .messages(VIEW_WC_COMMIT_IDS)
+            .find(|entry| entry.bytes(MAP_KEY) == Some(workspace_id.as_bytes()))
+            .and_then(|entry| entry.bytes(MAP_VALUE))
+            .or_else(|| view.bytes(VIEW_WC_COMMIT_ID_LEGACY))?
+            .to_vec();
+
+        let bookmarks = view
+            .messages(VIEW_BOOKMARKS)
+            .filter(|bookmark| {
+                // conflicted bookmarks have no single target, so they can't point here
+                bookmark
+                    .message(BOOKMARK_LOCAL_TARGET)
+                    .and_then(|target| target.bytes(REF_TARGET_COMMIT_ID))
+                    == Some(wc_commit_id.as_slice())
+            })
+            .filter_map(|bookmark| bookmark.bytes(BOOKMARK_NAME))
+            .map(|name| String::from_utf8_lossy(name).into_owned())
+            .collect();
+
+        Some(Jujutsu {
+            wc_commit_id,
+            bookmarks,
+        })
+    }
+}
+
+impl Vcs for Jujutsu {
+    fn identify(&self) -> Option<String> {
+        if self.bookmarks.is_empty() {
+            let id = hex(&self.wc_commit_id);
+
+            Some(id[..id.len().min(SHORT_ID_LEN)].to_string())
+        } else {
+            Some(self.bookmarks.join("\\"))
+        }
+    }
+
+    // the working copy is itself a commit, so there's never anything uncommitted
+    fn is_modified(&self) -> Option<bool> {
+        Some(false)
+    }
+
+    // nothing in jj is left half done
+    fn operation(&self) -> Option<String> {
+        None
+    }
+}
+
+// secondary workspaces have a file pointing to the repository instead
+fn repo_dir(jj: &Path) -> Option<PathBuf> {
+    let repo = jj.join("repo");
+
+    if repo.is_dir() {
+        Some(repo)
+    } else {
+        let target = fs::read_to_string(&repo).ok()?;
+
+        Some(jj.join(target.trim()))
+    }
+}
+
+enum Value<'a> {
+    Varint,
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+// just enough protobuf to pull out the fields we care about
+struct Message<'a> {
+    fields: Vec<(u32, Value<'a>)>,
+}
+
+impl<'a> Message<'a> {
+    fn parse(mut buf: &'a [u8]) -> Option<Message<'a>> {
+        let mut fields = Vec::new();
+
+        while !buf.is_empty() {
+            let key = varint(&mut buf)?;
+            let number = (key >> 3) as u32;
+
+            let value = match key & 0x7 {
+                0 => {
+                    varint(&mut buf)?;
+
+                    Value::Varint
+                }
+                1 | 5 => {
+                    let len = if key & 0x7 == 1 { 8 } else { 4 };
+                    buf = buf.get(len..)?;
+
+                    Value::Fixed
+                }
+                2 => {
+                    let len = varint(&mut buf)? as usize;
+                    let bytes = buf.get(..len)?;
+                    buf = &buf[len..];
+
+                    Value::Bytes(bytes)
+                }
+                _ => return None,
+            };
+
+            fields.push((number, value));
+        }
+
+        Some(Message { fields })
+    }
+
+    // the last occurrence wins, like in protobuf
+    fn bytes(&self, number: u32) -> Option<&'a [u8]> {
+        self.all_bytes(number).last()
+    }
+
+    fn message(&self, number: u32) -> Option<Message<'a>> {
+        Message::parse(self.bytes(number)?)
+    }
+
+    fn messages(&self, number: u32) -> impl Iterator<Item = Message<'a>> + '_ {
+        self.all_bytes(number).filter_map(Message::parse)
+    }
+
+    fn all_bytes(&self, number: u32) -> impl Iterator<Item = &'a [u8]> + '_ {
+        self.fields
+            .iter()
+            .filter_map(move |(n, value)| match value {
+                Value::Bytes(b) if *n == number => Some(*b),
+                _ => None,
+            })
+    }
+}
+
+fn varint(buf: &mut &[u8]) -> Option<u64> {
+    let mut value = 0;
+
+    for (i, &byte) in buf.iter().enumerate().take(10) {
+        value |= ((byte & 0x7f) as u64) << (7 * i);
+
+        if byte & 0x80 == 0 {
+            *buf = &buf[i + 1..];
+
+            return Some(value);
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(number: u32, bytes: &[u8]) -> Vec<u8> {
+        let mut field = vec![(number << 3 | 2) as u8, bytes.len() as u8];
+        field.extend_from_slice(bytes);
+
+        field
+    }
+
+    #[test]
+    fn message() {
+        let bookmark = [field(1, b"main"), field(2, &field(1, &[0xab; 4]))].concat();
+        // a varint, a fixed64 and two repeated fields
+        let view = [
+            vec![0x08, 0x96, 0x01],
+            vec![0x11, 1, 2, 3, 4, 5, 6, 7, 8],
+            field(5, &bookmark),
+            field(5, &field(1, b"other")),
+        ]
+        .concat();
+
+        let view = Message::parse(&view).unwrap();
+        let bookmarks: Vec<_> = view.messages(5).collect();
+
+        assert_eq!(bookmarks.len(), 2);
+        assert_eq!(bookmarks[0].bytes(1), Some(&b"main"[..]));
+        assert_eq!(
+            bookmarks[0].message(2).unwrap().bytes(1),
+            Some(&[0xab; 4][..])
+        );
+        assert_eq!(bookmarks[1].message(2).map(|_| ()), None);
+
+        // the length runs past the end
+        assert!(Message::parse(&[0x0a, 0x05, 0x00]).is_none());
+    }
+}
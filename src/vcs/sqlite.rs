@@ -0,0 +1,538 @@
+// Copyright (C) 2020 Gregory Meyer
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+// a read-only reader for just enough of the SQLite file format to scan small rowid tables. see
+// https://www.sqlite.org/fileformat.html
+
+use std::{collections::HashSet, convert::TryInto, fs::File, os::unix::fs::FileExt, path::Path};
+
+const HEADER_LEN: usize = 100;
+const LEAF_TABLE: u8 = 0x0d;
+const INTERIOR_TABLE: u8 = 0x05;
+const UTF8: u32 = 1;
+
+// real b-trees are nowhere near this deep
+const MAX_DEPTH: usize = 32;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+impl Value {
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            Value::Integer(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    pub fn as_text(&self) -> Option<&str> {
+        match self {
+            Value::Text(t) => Some(t),
+            _ => None,
+        }
+    }
+}
+
+pub struct Row<'t> {
+    columns: &'t [String],
+    values: &'t [Value],
+}
+
+impl<'t> Row<'t> {
+    // NULL if the column doesn't exist, like a column added after the row was written
+    pub fn get(&self, column: &str) -> &'t Value {
+        self.columns
+            .iter()
+            .position(|c| c.eq_ignore_ascii_case(column))
+            .and_then(|i| self.values.get(i))
+            .unwrap_or(&Value::Null)
+    }
+}
+
+pub struct Table {
+    columns: Vec<String>,
+    // values in column order, with INTEGER PRIMARY KEY columns filled in from the rowid
+    rows: Vec<Vec<Value>>,
+}
+
+impl Table {
+    pub fn rows(&self) -> impl Iterator<Item = Row<'_>> {
+        self.rows.iter().map(move |values| Row {
+            columns: &self.columns,
+            values,
+        })
+    }
+}
+
+pub struct Database {
+    file: File,
+    len: u64,
+    page_size: usize,
+    usable_size: usize,
+    // sqlite_schema is (type, name, tbl_name, rootpage, sql)
+    schema: Vec<Vec<Value>>,
+}
+
+impl Database {
+    pub fn open(path: &Path) -> Option<Database> {
+        let file = File::open(path).ok()?;
+        let len = file.metadata().ok()?.len();
+        let mut header = [0; HEADER_LEN];
+        file.read_exact_at(&mut header, 0).ok()?;
+
+        if !header.starts_with(b"SQLite format 3\0") || be_u32(&header[56..60]) != UTF8 {
+            return None;
+        }
+
+        let page_size = match u16::from_be_bytes([header[16], header[17]]) {
+            1 => 65536,
+            s => s as usize,
+        };
+        let usable_size = page_size.checked_sub(header[20] as usize)?;
+
+        // the smallest page the format allows, less what's reserved for extensions
+        if usable_size < 480 {
+            return None;
+        }
+
+        let mut database = Database {
+            file,
+            len,
+            page_size,
+            usable_size,
+            schema: Vec::new(),
+        };
+
+        // read once, since every table lookup needs it. it's rooted at page 1
+        let mut schema = Vec::new();
+        database.scan(1, 0, &mut HashSet::new(), &mut |_, values| {
+            schema.push(values);
+
+            false
+        })?;
+        database.schema = schema;
+
+        Some(database)
+    }
+
+    pub fn table(&self, name: &str) -> Option<Table> {
+        let (root_page, columns, rowid_column) = self.find_table(name)?;
+        let mut rows = Vec::new();
+
+        self.scan(root_page, 0, &mut HashSet::new(), &mut |rowid, values| {
+            rows.push(fill(values, rowid, columns.len(), rowid_column));
+
+            false
+        })?;
+
+        Some(Table { columns, rows })
+    }
+
+    // like table(name).rows().find_map(f), but stops reading once f returns something
+    pub fn find_map<T, F: FnMut(Row<'_>) -> Option<T>>(&self, name: &str, mut f: F) -> Option<T> {
+        let (root_page, columns, rowid_column) = self.find_table(name)?;
+        let mut found = None;
+
+        self.scan(root_page, 0, &mut HashSet::new(), &mut |rowid, values| {
+            let values = fill(values, rowid, columns.len(), rowid_column);
+            found = f(Row {
+                columns: &columns,
+                values: &values,
+            });
+
+            found.is_some()
+        })?;
+
+        found
+    }
+
+    // the root page, column names and rowid alias of a table
+    fn find_table(&self, name: &str) -> Option<(usize, Vec<String>, Option<usize>)> {
+        let values = self.schema.iter().find(|values| {
+            values.first().and_then(Value::as_text) == Some("table")
+                && values
+                    .get(1)
+                    .and_then(Value::as_text)
+                    .map(|n| n.eq_ignore_ascii_case(name))
+                    .unwrap_or(false)
+        })?;
+
+        let root_page = values.get(3)?.as_integer()?.try_into().ok()?;
+        let (columns, rowid_column) = parse_columns(values.get(4)?.as_text()?)?;
+
+        Some((root_page, columns, rowid_column))
+    }
+
+    fn page(&self, number: usize) -> Option<Vec<u8>> {
+        let mut page = vec![0; self.page_size];
+        let offset = number.checked_sub(1)?.checked_mul(self.page_size)?;
+        self.file.read_exact_at(&mut page, offset as u64).ok()?;
+
+        Some(page)
+    }
+
+    // passes every (rowid, record) in the table b-tree rooted at page_number to visit, until
+    // visit returns true. Some(true) if it did. a page that turns up twice means the file is
+    // corrupt, and would otherwise send us around in circles
+    fn scan(
+        &self,
+        page_number: usize,
+        depth: usize,
+        visited: &mut HashSet<usize>,
+        visit: &mut dyn FnMut(i64, Vec<Value>) -> bool,
+    ) -> Option<bool> {
+        if depth > MAX_DEPTH || !visited.insert(page_number) {
+            return None;
+        }
+
+        let page = self.page(page_number)?;
+        let header = if page_number == 1 { HEADER_LEN } else { 0 };
+
+        let kind = *page.get(header)?;
+        let cell_count = be_u16(page.get(header + 3..header + 5)?) as usize;
+        let header_len = if kind == INTERIOR_TABLE { 12 } else { 8 };
+
+        for i in 0..cell_count {
+            let pointer = header + header_len + 2 * i;
+            let cell = be_u16(page.get(pointer..pointer + 2)?) as usize;
+            let mut cell = page.get(cell..)?;
+
+            let done = match kind {
+                INTERIOR_TABLE => {
+                    let child = be_u32(cell.get(..4)?) as usize;
+                    self.scan(child, depth + 1, visited, visit)?
+                }
+                LEAF_TABLE => {
+                    let payload_len = varint(&mut cell)?;
+                    let rowid = varint(&mut cell)? as i64;
+                    let payload = self.payload(cell, payload_len)?;
+
+                    visit(rowid, parse_record(&payload)?)
+                }
+                _ => return None,
+            };
+
+            if done {
+                return Some(true);
+            }
+        }
+
+        if kind == INTERIOR_TABLE {
+            let right = be_u32(page.get(header + 8..header + 12)?) as usize;
+
+            return self.scan(right, depth + 1, visited, visit);
+        }
+
+        Some(false)
+    }
+
+    // gathers a payload that may have spilled onto overflow pages
+    fn payload(&self, cell: &[u8], len: u64) -> Option<Vec<u8>> {
+        // a corrupt length could otherwise have us allocate far more than the file holds
+        if len > self.len {
+            return None;
+        }
+
+        let len = len as usize;
+        let usable = self.usable_size;
+        let max_local = usable - 35;
+
+        let local_len = if len <= max_local {
+            len
+        } else {
+            let min_local = (usable - 12) * 32 / 255 - 23;
+            let k = min_local + (len - min_local) % (usable - 4);
+
+            if k <= max_local {
+                k
+            } else {
+                min_local
+            }
+        };
+
+        let mut payload = cell.get(..local_len)?.to_vec();
+
+        if local_len < len {
+            let mut next = be_u32(cell.get(local_len..local_len + 4)?) as usize;
+            let mut visited = HashSet::new();
+
+            while payload.len() < len {
+                if !visited.insert(next) {
+                    return None;
+                }
+
+                let page = self.page(next)?;
+                let remaining = len - payload.len();
+                let chunk = remaining.min(usable - 4);
+
+                payload.extend_from_slice(page.get(4..4 + chunk)?);
+                next = be_u32(page.get(..4)?) as usize;
+            }
+        }
+
+        Some(payload)
+    }
+}
+
+// values in column order, with INTEGER PRIMARY KEY columns filled in from the rowid
+fn fill(mut values: Vec<Value>, rowid: i64, len: usize, rowid_column: Option<usize>) -> Vec<Value> {
+    values.resize(len, Value::Null);
+
+    if let Some(i) = rowid_column {
+        values[i] = Value::Integer(rowid);
+    }
+
+    values
+}
+
+fn parse_record(mut record: &[u8]) -> Option<Vec<Value>> {
+    let all = record;
+    let header_len = varint(&mut record)? as usize;
+    let mut header = all.get(all.len() - record.len()..header_len)?;
+    let mut body = all.get(header_len..)?;
+
+    let mut values = Vec::new();
+
+    while !header.is_empty() {
+        let serial_type = varint(&mut header)?;
+
+        let (value, len) = match serial_type {
+            0 => (Value::Null, 0),
+            1..=6 => {
+                let len = [1, 2, 3, 4, 6, 8][serial_type as usize - 1];
+                let bytes = body.get(..len)?;
+
+                // sign-extend from the first byte
+                let mut value = if bytes[0] & 0x80 != 0 { -1i64 } else { 0 };
+
+                for &b in bytes {
+                    value = (value << 8) | b as i64;
+                }
+
+                (Value::Integer(value), len)
+            }
+            7 => (
+                Value::Real(f64::from_bits(u64::from_be_bytes(
+                    body.get(..8)?.try_into().ok()?,
+                ))),
+                8,
+            ),
+            8 => (Value::Integer(0), 0),
+            9 => (Value::Integer(1), 0),
+            n if n >= 12 && n % 2 == 0 => {
+                let len = (n as usize - 12) / 2;
+
+                (Value::Blob(body.get(..len)?.to_vec()), len)
+            }
+            n if n >= 13 => {
+                let len = (n as usize - 13) / 2;
+                let text = String::from_utf8_lossy(body.get(..len)?).into_owned();
+
+                (Value::Text(text), len)
+            }
+            _ => return None,
+        };
+
+        values.push(value);
+        body = &body[len..];
+    }
+
+    Some(values)
+}
+
+// the column names in a CREATE TABLE statement, and which one is an alias for the rowid
+fn parse_columns(sql: &str) -> Option<(Vec<String>, Option<usize>)> {
+    const CONSTRAINTS: &[&str] = &["CONSTRAINT", "PRIMARY", "UNIQUE", "CHECK", "FOREIGN"];
+
+    let body = sql.get(sql.find('(')? + 1..sql.rfind(')')?)?;
+
+    let mut definitions = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+
+    for (i, c) in body.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                definitions.push(&body[start..i]);
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+
+    definitions.push(&body[start..]);
+
+    let mut columns = Vec::new();
+    let mut rowid_column = None;
+
+    for definition in definitions {
+        // table constraints like UNIQUE(a,b) needn't have a space before the parenthesis
+        let words: Vec<_> = definition
+            .split(|c: char| c.is_whitespace() || c == '(')
+            .filter(|w| !w.is_empty())
+            .collect();
+        let name = match words.first() {
+            Some(n) => n,
+            None => continue,
+        };
+
+        if CONSTRAINTS.iter().any(|c| c.eq_ignore_ascii_case(name)) {
+            continue;
+        }
+
+        let upper = words[1..].join(" ").to_ascii_uppercase();
+
+        if upper.starts_with("INTEGER PRIMARY KEY") {
+            rowid_column = Some(columns.len());
+        }
+
+        columns.push(name.trim_matches(|c| c == '"' || c == '`').to_string());
+    }
+
+    Some((columns, rowid_column))
+}
+
+fn varint(buf: &mut &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+
+    for (i, &byte) in buf.iter().enumerate().take(9) {
+        if i == 8 {
+            value = (value << 8) | byte as u64;
+            *buf = &buf[9..];
+
+            return Some(value);
+        }
+
+        value = (value << 7) | (byte & 0x7f) as u64;
+
+        if byte & 0x80 == 0 {
+            *buf = &buf[i + 1..];
+
+            return Some(value);
+        }
+    }
+
+    None
+}
+
+fn be_u16(bytes: &[u8]) -> u16 {
+    u16::from_be_bytes([bytes[0], bytes[1]])
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes(bytes[..4].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{env, fs, path::PathBuf, process};
+
+    // a database of 512-byte pages whose first page, the schema's root, is page
+    fn database(name: &str, page: &[u8]) -> PathBuf {
+        let mut file = vec![0; 512];
+        file[..16].copy_from_slice(b"SQLite format 3\0");
+        file[16..18].copy_from_slice(&512u16.to_be_bytes());
+        file[56..60].copy_from_slice(&UTF8.to_be_bytes());
+        file[HEADER_LEN..HEADER_LEN + page.len()].copy_from_slice(page);
+
+        let path = env::temp_dir().join(format!("clanker-{}-{}.db", name, process::id()));
+        fs::write(&path, file).unwrap();
+
+        path
+    }
+
+    #[test]
+    fn cyclic_pages() {
+        // an interior page whose two cells and right child all point back at itself
+        let mut page = vec![INTERIOR_TABLE, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 1];
+        page.extend_from_slice(&[0, 200, 0, 200]);
+        page.resize(100, 0);
+        page.extend_from_slice(&[0, 0, 0, 1, 0]);
+
+        let path = database("cyclic_pages", &page);
+        assert!(Database::open(&path).is_none());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn oversized_payload() {
+        let path = database("oversized_payload", &[LEAF_TABLE, 0, 0, 0, 0, 0, 0, 0]);
+        let database = Database::open(&path).unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert!(database.table("vfile").is_none());
+        assert!(database.payload(&[0; 16], 1 << 40).is_none());
+        assert_eq!(database.payload(&[1, 2, 3], 2), Some(vec![1, 2]));
+    }
+
+    #[test]
+    fn record() {
+        // header of 5 bytes: NULL, a 1-byte integer, 3 bytes of text, the constant 1
+        let record = [5, 0, 1, 19, 9, 0xff, b'a', b'b', b'c'];
+
+        assert_eq!(
+            parse_record(&record),
+            Some(vec![
+                Value::Null,
+                Value::Integer(-1),
+                Value::Text("abc".to_string()),
+                Value::Integer(1),
+            ])
+        );
+    }
+
+    #[test]
+    fn columns() {
+        let sql = "CREATE TABLE vfile(\n  id INTEGER PRIMARY KEY,\n  vid INTEGER REFERENCES \
+                   blob,\n  mtime INTEGER,\n  pathname TEXT,\n  UNIQUE(pathname,vid)\n)";
+
+        assert_eq!(
+            parse_columns(sql),
+            Some((
+                vec![
+                    "id".to_string(),
+                    "vid".to_string(),
+                    "mtime".to_string(),
+                    "pathname".to_string(),
+                ],
+                Some(0),
+            ))
+        );
+    }
+
+    #[test]
+    fn malformed_columns() {
+        assert_eq!(parse_columns("CREATE TABLE t) ("), None);
+        assert_eq!(parse_columns("CREATE TABLE t"), None);
+    }
+
+    #[test]
+    fn varints() {
+        let mut buf: &[u8] = &[0x81, 0x00, 0x7f];
+        assert_eq!(varint(&mut buf), Some(128));
+        assert_eq!(varint(&mut buf), Some(127));
+        assert_eq!(varint(&mut buf), None);
+    }
+}